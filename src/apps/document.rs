@@ -0,0 +1,391 @@
+use crate::{Error, Result};
+use pest::Parser;
+use std::{fmt::Display, str::FromStr};
+
+#[derive(pest_derive::Parser)]
+#[grammar = "common/ini.pest"]
+struct IniParser;
+
+/// An ini-style file such as mimeapps.list, kept line by line so that
+/// comments, ordering and unknown sections survive being written back
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Document {
+    lines: Vec<Line>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Line {
+    /// The line exactly as it was read, including its line ending
+    text: String,
+    kind: Kind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Kind {
+    Section(String),
+    Property { key: String, value: String },
+    Other,
+}
+
+impl FromStr for Document {
+    type Err = Error;
+    fn from_str(raw: &str) -> Result<Self> {
+        let mut lines = raw
+            .split_inclusive('\n')
+            .map(|text| Line {
+                text: text.to_owned(),
+                kind: Kind::Other,
+            })
+            .collect::<Vec<_>>();
+
+        let file = IniParser::parse(Rule::file, raw)
+            .map_err(Box::new)?
+            .next()
+            .unwrap();
+
+        // Where each line starts, to find the line a pair is on
+        let starts = lines
+            .iter()
+            .scan(0, |start, line| {
+                let line_start = *start;
+                *start += line.text.len();
+                Some(line_start)
+            })
+            .collect::<Vec<_>>();
+
+        for pair in file.into_inner() {
+            let start = pair.as_span().start();
+            let kind = match pair.as_rule() {
+                Rule::section => Kind::Section(pair.into_inner().concat()),
+                Rule::property => {
+                    let mut inner_rules = pair.into_inner(); // { name ~ "=" ~ value }
+                    Kind::Property {
+                        key: inner_rules.next().unwrap().as_str().trim().into(),
+                        value: inner_rules.next().unwrap().as_str().into(),
+                    }
+                }
+                _ => continue,
+            };
+            let index = starts.partition_point(|&s| s <= start) - 1;
+            lines[index].kind = kind;
+        }
+
+        Ok(Self { lines })
+    }
+}
+
+impl Display for Document {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.lines.iter().try_for_each(|l| f.write_str(&l.text))
+    }
+}
+
+impl Line {
+    fn ending(&self) -> &str {
+        let content = self.text.trim_end_matches(&['\r', '\n'][..]);
+        &self.text[content.len()..]
+    }
+}
+
+impl Document {
+    /// Every property of the sections with the given name, in file order
+    pub fn properties<'a>(
+        &'a self,
+        section: &'a str,
+    ) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+        let mut current = None;
+        self.lines.iter().filter_map(move |line| match &line.kind {
+            Kind::Section(name) => {
+                current = Some(name.clone());
+                None
+            }
+            Kind::Property { key, value }
+                if current.as_deref() == Some(section) =>
+            {
+                Some((key.as_str(), value.as_str()))
+            }
+            _ => None,
+        })
+    }
+
+    /// Set a property in every section with the given name, rewriting only
+    /// the lines that hold it and keeping the spacing around their `=`. New
+    /// properties go after the last property of the last such section,
+    /// creating it if need be.
+    pub fn set(&mut self, section: &str, key: &str, value: &str) {
+        let newline = self.newline().to_owned();
+        let mut current = None;
+        let mut found = false;
+        let mut insert_at = None;
+
+        for (i, line) in self.lines.iter_mut().enumerate() {
+            let is_key = match &line.kind {
+                Kind::Section(name) => {
+                    current = Some(name.clone());
+                    if name == section {
+                        insert_at = Some(i + 1);
+                    }
+                    continue;
+                }
+                Kind::Property { key: k, .. }
+                    if current.as_deref() == Some(section) =>
+                {
+                    insert_at = Some(i + 1);
+                    k == key
+                }
+                _ => continue,
+            };
+
+            if is_key {
+                let ending = line.ending().to_owned();
+                let separator = line.text.find('=').unwrap() + 1;
+                let old = &line.text[separator..line.text.len() - ending.len()];
+                let value = format!(
+                    "{}{}",
+                    &old[..old.len() - old.trim_start().len()],
+                    value
+                );
+                line.text =
+                    format!("{}{}{}", &line.text[..separator], value, ending);
+                line.kind = Kind::Property {
+                    key: key.into(),
+                    value,
+                };
+                found = true;
+            }
+        }
+
+        if found {
+            return;
+        }
+
+        let property = Line {
+            text: format!("{}={}{}", key, value, newline),
+            kind: Kind::Property {
+                key: key.into(),
+                value: value.into(),
+            },
+        };
+
+        match insert_at {
+            Some(i) => {
+                self.terminate_line(i);
+                self.lines.insert(i, property);
+            }
+            None => {
+                let len = self.lines.len();
+                self.terminate_line(len);
+                if self.lines.last().is_some_and(|l| !l.text.trim().is_empty())
+                {
+                    self.lines.push(Line {
+                        text: newline.clone(),
+                        kind: Kind::Other,
+                    });
+                }
+                self.lines.push(Line {
+                    text: format!("[{}]{}", section, newline),
+                    kind: Kind::Section(section.into()),
+                });
+                self.lines.push(property);
+            }
+        }
+    }
+
+    /// Remove every occurence of a property from the sections with the given
+    /// name
+    pub fn remove(&mut self, section: &str, key: &str) {
+        let mut current = None;
+        self.lines.retain(|line| match &line.kind {
+            Kind::Section(name) => {
+                current = Some(name.clone());
+                true
+            }
+            Kind::Property { key: k, .. } => {
+                !(k == key && current.as_deref() == Some(section))
+            }
+            Kind::Other => true,
+        });
+    }
+
+    /// The line ending used throughout the file
+    fn newline(&self) -> &str {
+        self.lines
+            .iter()
+            .map(Line::ending)
+            .find(|e| !e.is_empty())
+            .unwrap_or("\n")
+    }
+
+    /// Make sure the line before `index` ends with a line break, so that a
+    /// line can be inserted at `index`
+    fn terminate_line(&mut self, index: usize) {
+        let newline = self.newline().to_owned();
+        if let Some(line) = index.checked_sub(1).map(|i| &mut self.lines[i]) {
+            if line.ending().is_empty() {
+                line.text.push_str(&newline);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(name: &str) -> String {
+        std::fs::read_to_string(format!("tests/mimeapps/{}", name)).unwrap()
+    }
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        for name in &[
+            "kde.list",
+            "crlf.list",
+            "hand-edited.list",
+            "no-trailing-newline.list",
+        ] {
+            let raw = read(name);
+            assert_eq!(raw.parse::<Document>()?.to_string(), raw, "{}", name);
+        }
+
+        assert_eq!("".parse::<Document>()?.to_string(), "");
+        let lone_cr = "[Default Applications]\ra=b\r\nc=d\re=f\n";
+        assert_eq!(lone_cr.parse::<Document>()?.to_string(), lone_cr);
+
+        Ok(())
+    }
+
+    #[test]
+    fn properties() -> Result<()> {
+        let doc = read("hand-edited.list").parse::<Document>()?;
+
+        assert_eq!(
+            doc.properties("Default Applications").collect::<Vec<_>>(),
+            [
+                (
+                    "x-scheme-handler/https",
+                    "firefox.desktop;chromium.desktop;"
+                ),
+                ("x-scheme-handler/http", "firefox.desktop;chromium.desktop;"),
+                ("text/html", " firefox.desktop;"),
+                ("application/pdf", "org.pwmt.zathura.desktop;"),
+                ("inode/directory", "thunar.desktop;"),
+            ]
+        );
+        assert_eq!(
+            doc.properties("Added Associations").collect::<Vec<_>>(),
+            [("image/png", "feh.desktop;"), ("audio/flac", "")]
+        );
+        assert_eq!(doc.properties("Missing").count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn edits_only_touched_lines() -> Result<()> {
+        let raw = read("hand-edited.list");
+        let mut doc = raw.parse::<Document>()?;
+
+        doc.set("Default Applications", "text/html", "brave.desktop;");
+        doc.set("Default Applications", "image/gif", "feh.desktop;");
+        doc.remove("Added Associations", "image/png");
+
+        assert_eq!(
+            doc.to_string(),
+            raw.replace(
+                "text/html = firefox.desktop;",
+                "text/html = brave.desktop;"
+            )
+            .replace(
+                "inode/directory=thunar.desktop;\n",
+                "inode/directory=thunar.desktop;\nimage/gif=feh.desktop;\n"
+            )
+            .replace("image/png=feh.desktop;\n", "")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn lone_carriage_returns() -> Result<()> {
+        // Not a line break, so the rest of the line is part of the value
+        let raw = "[Default Applications]\n\
+                   text/html=a.desktop;\rtext/plain=b.desktop;\n\
+                   image/png=c.desktop;\n";
+        let mut doc = raw.parse::<Document>()?;
+        assert_eq!(
+            doc.properties("Default Applications").collect::<Vec<_>>(),
+            [
+                ("text/html", "a.desktop;\rtext/plain=b.desktop;"),
+                ("image/png", "c.desktop;")
+            ]
+        );
+
+        doc.set("Default Applications", "text/plain", "ed.desktop;");
+        doc.set("Default Applications", "image/png", "feh.desktop;");
+        assert_eq!(
+            doc.to_string(),
+            raw.replace(
+                "c.desktop;\n",
+                "feh.desktop;\ntext/plain=ed.desktop;\n"
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn repeated_sections() -> Result<()> {
+        let mut doc = "[Default Applications]\n\
+                       text/html = firefox.desktop;\n\
+                       [Added Associations]\n\
+                       image/png=feh.desktop;\n\
+                       [Default Applications]\n\
+                       text/html=chromium.desktop;\n"
+            .parse::<Document>()?;
+
+        doc.set("Default Applications", "text/html", "brave.desktop;");
+        doc.set("Default Applications", "image/gif", "feh.desktop;");
+        assert_eq!(
+            doc.to_string(),
+            "[Default Applications]\n\
+             text/html = brave.desktop;\n\
+             [Added Associations]\n\
+             image/png=feh.desktop;\n\
+             [Default Applications]\n\
+             text/html=brave.desktop;\n\
+             image/gif=feh.desktop;\n"
+        );
+        assert_eq!(doc, doc.to_string().parse()?);
+
+        doc.remove("Default Applications", "text/html");
+        assert_eq!(
+            doc.properties("Default Applications").collect::<Vec<_>>(),
+            [("image/gif", "feh.desktop;")]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn appends_sections() -> Result<()> {
+        let mut doc = read("no-trailing-newline.list").parse::<Document>()?;
+        doc.set("Default Applications", "text/html", "firefox.desktop;");
+        doc.set("Added Associations", "text/html", "brave.desktop;");
+
+        assert_eq!(
+            doc.to_string(),
+            "[Default Applications]\ntext/plain=nvim.desktop;\n\
+             text/html=firefox.desktop;\n\
+             \n[Added Associations]\ntext/html=brave.desktop;\n"
+        );
+
+        let mut doc = read("crlf.list").parse::<Document>()?;
+        doc.set("Removed Associations", "video/mp4", "vlc.desktop;");
+        assert!(doc.to_string().ends_with(
+            "\r\n[Removed Associations]\r\nvideo/mp4=vlc.desktop;\r\n"
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+use crate::{apps::storage, common::Handler, Result};
+use mime::Mime;
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Only the most recent changes are remembered
+const MAX_ENTRIES: usize = 500;
+
+/// Everything a single handlr command changed in mimeapps.list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+    pub changes: Vec<Change>,
+}
+
+/// The handlers of a mime in one section of mimeapps.list, before and after
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub section: String,
+    pub mime: Mime,
+    pub old: Vec<Handler>,
+    pub new: Vec<Handler>,
+}
+
+impl Entry {
+    pub fn new(changes: Vec<Change>) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            changes,
+        }
+    }
+
+    /// How long ago the entry was recorded, e.g. `5m ago`
+    pub fn age(&self) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        match now.saturating_sub(self.timestamp) {
+            s if s < 60 => "just now".into(),
+            s if s < 60 * 60 => format!("{}m ago", s / 60),
+            s if s < 60 * 60 * 24 => format!("{}h ago", s / (60 * 60)),
+            s => format!("{}d ago", s / (60 * 60 * 24)),
+        }
+    }
+
+    fn to_json(&self) -> json::JsonValue {
+        let handlers = |handlers: &[Handler]| {
+            handlers.iter().map(|h| h.to_string()).collect::<Vec<_>>()
+        };
+
+        json::object! {
+            timestamp: self.timestamp,
+            changes: self.changes.iter().map(|c| json::object! {
+                section: c.section.as_str(),
+                mime: c.mime.essence_str(),
+                old: handlers(&c.old),
+                new: handlers(&c.new),
+            }).collect::<Vec<_>>(),
+        }
+    }
+
+    fn from_json(value: &json::JsonValue) -> Option<Self> {
+        let handlers = |value: &json::JsonValue| {
+            value
+                .members()
+                .filter_map(|h| Some(Handler::assume_valid(h.as_str()?.into())))
+                .collect()
+        };
+
+        Some(Self {
+            timestamp: value["timestamp"].as_u64()?,
+            changes: value["changes"]
+                .members()
+                .filter_map(|c| {
+                    Some(Change {
+                        section: c["section"].as_str()?.to_owned(),
+                        mime: Mime::from_str(c["mime"].as_str()?).ok()?,
+                        old: handlers(&c["old"]),
+                        new: handlers(&c["new"]),
+                    })
+                })
+                .collect(),
+        })
+    }
+}
+
+pub fn path() -> Result<PathBuf> {
+    Ok(xdg::BaseDirectories::with_prefix("handlr")?
+        .place_data_file("history.jsonl")?)
+}
+
+/// All recorded entries, oldest first
+pub fn read(path: &Path) -> Result<Vec<Entry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    std::fs::read_to_string(path)?
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| Ok(json::parse(l)?))
+        .filter_map(|value: Result<_>| match value {
+            Ok(value) => Entry::from_json(&value).map(Ok),
+            Err(e) => Some(Err(e)),
+        })
+        .collect()
+}
+
+fn write(path: &Path, entries: &[Entry]) -> Result<()> {
+    let start = entries.len().saturating_sub(MAX_ENTRIES);
+    let contents = entries[start..]
+        .iter()
+        .map(|e| e.to_json().dump() + "\n")
+        .collect::<String>();
+
+    storage::write_atomic(path, contents.as_bytes())
+}
+
+pub fn record(path: &Path, entry: Entry) -> Result<()> {
+    let mut entries = read(path)?;
+    entries.push(entry);
+    write(path, &entries)
+}
+
+/// Forget the `count` most recent entries
+pub fn forget(path: &Path, count: usize) -> Result<()> {
+    let mut entries = read(path)?;
+    entries.truncate(entries.len().saturating_sub(count));
+    write(path, &entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_round_trip() {
+        let entry = Entry::new(vec![
+            Change {
+                section: "Default Applications".into(),
+                mime: mime::APPLICATION_PDF,
+                old: vec![Handler::assume_valid("evince.desktop".into())],
+                new: vec![
+                    Handler::assume_valid("zathura.desktop".into()),
+                    Handler::assume_valid("evince.desktop".into()),
+                ],
+            },
+            Change {
+                section: "Added Associations".into(),
+                mime: mime::TEXT_PLAIN,
+                old: vec![],
+                new: vec![Handler::assume_valid("nvim.desktop".into())],
+            },
+        ]);
+
+        let parsed = json::parse(&entry.to_json().dump()).unwrap();
+        assert_eq!(Entry::from_json(&parsed), Some(entry));
+    }
+}
@@ -0,0 +1,370 @@
+use crate::{
+    apps::{storage, system},
+    common::{desktop_id, Action, DesktopEntry},
+    Result,
+};
+use mime::Mime;
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The version of the cache's layout, to be bumped whenever what is cached
+/// changes so that older caches are rebuilt rather than misread
+const FORMAT: u32 = 1;
+
+/// Parsed .desktop files of every applications dir, so that listing them
+/// doesn't mean parsing hundreds of files every time
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(super) struct Index {
+    dirs: Vec<Dir>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Dir {
+    path: PathBuf,
+    files: Vec<File>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct File {
+    /// The desktop file ID
+    name: String,
+    modified: Option<SystemTime>,
+    /// None if the file couldn't be parsed
+    entry: Option<DesktopEntry>,
+}
+
+/// Every valid .desktop file, only the first of each name in order of
+/// precedence
+pub fn entries() -> Result<Vec<(OsString, DesktopEntry)>> {
+    Ok(load()?.entries())
+}
+
+/// The cached index, brought up to date
+pub(super) fn load() -> Result<Index> {
+    let cached = read().unwrap_or_default();
+    let index = cached.update(desktop_id::applications_dirs()?);
+
+    if index != cached {
+        // Not being able to cache is no reason to fail
+        let _ = write(&index);
+    }
+
+    Ok(index)
+}
+
+/// Parse every .desktop file again, ignoring what's cached
+pub fn rebuild() -> Result<()> {
+    write(&Index::default().update(desktop_id::applications_dirs()?))
+}
+
+fn path() -> Result<PathBuf> {
+    Ok(xdg::BaseDirectories::with_prefix("handlr")?
+        .place_cache_file("desktop-entries.json")?)
+}
+
+fn read() -> Option<Index> {
+    let raw = std::fs::read_to_string(path().ok()?).ok()?;
+    Index::from_json(&json::parse(&raw).ok()?)
+}
+
+fn write(index: &Index) -> Result<()> {
+    storage::write_atomic(&path()?, index.to_json().dump().as_bytes())
+}
+
+impl Index {
+    /// Bring the index up to date with the given dirs, only parsing files
+    /// that were added or modified since
+    fn update(&self, dirs: Vec<PathBuf>) -> Self {
+        let mut cached = self
+            .dirs
+            .iter()
+            .map(|dir| (&dir.path, dir))
+            .collect::<HashMap<_, _>>();
+
+        let dirs = dirs
+            .into_iter()
+            .map(|path| {
+                let cached = cached.remove(&path);
+
+                // A dir's mtime only changes when files are added, removed
+                // or renamed, not when one is edited in place, so every
+                // file has to be checked anyway. Listing is cheap next to
+                // parsing.
+                let files = desktop_id::list(&path)
+                    .into_iter()
+                    .filter_map(|(id, file)| {
+                        let name = id.into_string().ok()?;
+                        let modified = system::modified(&file)?;
+                        let cached = cached.and_then(|dir| {
+                            dir.files.iter().find(|f| {
+                                f.name == name && f.modified == Some(modified)
+                            })
+                        });
+
+                        Some(match cached {
+                            Some(file) => file.clone(),
+                            None => File {
+                                entry: DesktopEntry::read(
+                                    name.clone().into(),
+                                    file,
+                                )
+                                .ok(),
+                                name,
+                                modified: Some(modified),
+                            },
+                        })
+                    })
+                    .collect();
+
+                Dir { path, files }
+            })
+            .collect();
+
+        Self { dirs }
+    }
+
+    /// The valid entries of a single dir
+    pub(super) fn entries_in(&self, path: &Path) -> Vec<DesktopEntry> {
+        self.dirs
+            .iter()
+            .filter(|dir| dir.path == path)
+            .flat_map(|dir| dir.files.iter())
+            .filter_map(|file| file.entry.clone())
+            .collect()
+    }
+
+    /// Hidden entries count as deleted, but still shadow those of lower
+    /// precedence
+    fn entries(&self) -> Vec<(OsString, DesktopEntry)> {
+        let mut seen = HashSet::new();
+        self.dirs
+            .iter()
+            .flat_map(|dir| dir.files.iter())
+            .filter(|file| seen.insert(&file.name))
+            .filter_map(|file| {
+                Some((file.name.clone().into(), file.entry.clone()?))
+            })
+            .filter(|(_, entry)| !entry.hidden)
+            .collect()
+    }
+
+    fn to_json(&self) -> json::JsonValue {
+        json::object! {
+            format: FORMAT,
+            locale: crate::utils::messages_locale(),
+            dirs: self.dirs.iter().map(|dir| json::object! {
+                path: dir.path.to_string_lossy().as_ref(),
+                files: dir.files.iter().map(|file| json::object! {
+                    name: file.name.as_str(),
+                    modified: file.modified.map(to_nanos),
+                    entry: file.entry.as_ref().map(entry_to_json),
+                }).collect::<Vec<_>>(),
+            }).collect::<Vec<_>>(),
+        }
+    }
+
+    /// None if the index is unreadable, of another format or holds names in
+    /// another language
+    fn from_json(value: &json::JsonValue) -> Option<Self> {
+        if value["format"].as_u32()? != FORMAT
+            || value["locale"].as_str().map(str::to_owned)
+                != crate::utils::messages_locale()
+        {
+            return None;
+        }
+
+        let dirs = value["dirs"]
+            .members()
+            .map(|dir| {
+                Some(Dir {
+                    path: dir["path"].as_str()?.into(),
+                    files: dir["files"]
+                        .members()
+                        .map(|file| {
+                            Some(File {
+                                name: file["name"].as_str()?.into(),
+                                modified: file["modified"]
+                                    .as_u64()
+                                    .map(from_nanos),
+                                entry: match &file["entry"] {
+                                    json::JsonValue::Null => None,
+                                    entry => Some(entry_from_json(entry)?),
+                                },
+                            })
+                        })
+                        .collect::<Option<_>>()?,
+                })
+            })
+            .collect::<Option<_>>()?;
+
+        Some(Self { dirs })
+    }
+}
+
+fn to_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+fn from_nanos(nanos: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_nanos(nanos)
+}
+
+fn entry_to_json(entry: &DesktopEntry) -> json::JsonValue {
+    json::object! {
+        name: entry.name.as_str(),
+        generic_name: entry.generic_name.as_deref(),
+        comment: entry.comment.as_deref(),
+        exec: entry.exec.as_str(),
+        icon: entry.icon.as_deref(),
+        file_name: entry.file_name.to_string_lossy().as_ref(),
+        path: entry.path.to_string_lossy().as_ref(),
+        working_dir: entry.working_dir.as_ref().map(|dir| dir.to_string_lossy().into_owned()),
+        terminal: entry.terminal,
+        mimes: entry.mimes.iter().map(|m| m.essence_str()).collect::<Vec<_>>(),
+        categories: entry.categories.keys().map(String::as_str).collect::<Vec<_>>(),
+        try_exec: entry.try_exec.as_deref(),
+        hidden: entry.hidden,
+        no_display: entry.no_display,
+        only_show_in: entry.only_show_in.clone(),
+        not_show_in: entry.not_show_in.clone(),
+        url: entry.url.as_deref(),
+        dbus_activatable: entry.dbus_activatable,
+        actions: entry.actions.iter().map(|action| json::object! {
+            id: action.id.as_str(),
+            name: action.name.as_str(),
+            exec: action.exec.as_str(),
+            icon: action.icon.as_deref(),
+        }).collect::<Vec<_>>(),
+    }
+}
+
+fn entry_from_json(value: &json::JsonValue) -> Option<DesktopEntry> {
+    Some(DesktopEntry {
+        name: value["name"].as_str()?.into(),
+        generic_name: value["generic_name"].as_str().map(Into::into),
+        comment: value["comment"].as_str().map(Into::into),
+        exec: value["exec"].as_str()?.into(),
+        icon: value["icon"].as_str().map(Into::into),
+        file_name: value["file_name"].as_str()?.into(),
+        path: value["path"].as_str()?.into(),
+        working_dir: value["working_dir"].as_str().map(Into::into),
+        terminal: value["terminal"].as_bool()?,
+        mimes: value["mimes"]
+            .members()
+            .filter_map(|m| Mime::from_str(m.as_str()?).ok())
+            .collect(),
+        categories: value["categories"]
+            .members()
+            .filter_map(|c| Some((c.as_str()?.to_owned(), ())))
+            .collect(),
+        try_exec: value["try_exec"].as_str().map(Into::into),
+        hidden: value["hidden"].as_bool()?,
+        no_display: value["no_display"].as_bool()?,
+        only_show_in: strings(&value["only_show_in"]),
+        not_show_in: strings(&value["not_show_in"]),
+        url: value["url"].as_str().map(Into::into),
+        dbus_activatable: value["dbus_activatable"].as_bool()?,
+        action: None,
+        actions: value["actions"]
+            .members()
+            .map(|action| {
+                Some(Action {
+                    id: action["id"].as_str()?.into(),
+                    name: action["name"].as_str()?.into(),
+                    exec: action["exec"].as_str()?.into(),
+                    icon: action["icon"].as_str().map(Into::into),
+                })
+            })
+            .collect::<Option<_>>()?,
+    })
+}
+
+fn strings(value: &json::JsonValue) -> Vec<String> {
+    value
+        .members()
+        .filter_map(|s| Some(s.as_str()?.to_owned()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::fs;
+
+    #[test]
+    fn shadowing() {
+        let index = Index::default().update(vec![
+            PathBuf::from("tests/applications/user"),
+            PathBuf::from("tests/applications/system"),
+        ]);
+
+        let entries = index
+            .entries()
+            .into_iter()
+            .map(|(id, entry)| (id.into_string().unwrap(), entry.name))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            [
+                ("bar.desktop".into(), "Bar (user)".into()),
+                ("baz.desktop".into(), "Baz".into()),
+                ("gnome-calculator.desktop".into(), "Calculator".into()),
+                ("kde-apps-dolphin.desktop".into(), "Dolphin".into()),
+                ("kde-okular.desktop".into(), "Okular".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn reparses_modified_files() -> Result<()> {
+        let dir = TempDir::new("index");
+
+        let entry = |name: &str| {
+            format!("[Desktop Entry]\nName={}\nExec={}\n", name, name)
+        };
+        fs::write(dir.join("a.desktop"), entry("a"))?;
+        fs::write(dir.join("b.desktop"), entry("b"))?;
+        fs::write(dir.join("broken.desktop"), "")?;
+
+        let index = Index::default().update(vec![dir.to_path_buf()]);
+        assert_eq!(
+            Index::from_json(&json::parse(&index.to_json().dump())?),
+            Some(index.clone())
+        );
+        let mut older = index.to_json();
+        older["format"] = (FORMAT - 1).into();
+        assert_eq!(Index::from_json(&older), None);
+
+        // Same mtime, so the cached entry is kept
+        let a = fs::metadata(dir.join("a.desktop"))?.modified()?;
+        fs::write(dir.join("a.desktop"), entry("renamed"))?;
+        fs::File::options()
+            .write(true)
+            .open(dir.join("a.desktop"))?
+            .set_modified(a)?;
+
+        fs::write(dir.join("b.desktop"), entry("renamed"))?;
+        fs::File::options()
+            .write(true)
+            .open(dir.join("b.desktop"))?
+            .set_modified(SystemTime::now() + Duration::from_secs(60))?;
+
+        let names = index
+            .update(vec![dir.to_path_buf()])
+            .entries()
+            .into_iter()
+            .map(|(_, e)| e.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "renamed"]);
+
+        Ok(())
+    }
+}
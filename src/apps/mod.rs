@@ -1,5 +1,12 @@
+mod document;
+mod history;
+mod index;
+mod storage;
 mod system;
+mod trace;
 mod user;
 
+pub use document::{Document, Rule as MimeappsRule};
+pub use index::rebuild as rebuild_index;
 pub use system::SystemApps;
-pub use user::{MimeApps, Rule as MimeappsRule, APPS};
+pub use user::{MimeApps, APPS};
@@ -0,0 +1,198 @@
+use crate::{apps::history, Result};
+use std::{
+    fs::{self, File},
+    io::Write,
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The files kept along with the user's mimeapps.list to change it safely
+pub struct Storage {
+    pub lock: PathBuf,
+    pub history: PathBuf,
+    /// How many backups of mimeapps.list to keep
+    pub backups: usize,
+}
+
+impl Storage {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            lock: xdg::BaseDirectories::with_prefix("handlr")?
+                .place_config_file("mimeapps.lock")?,
+            history: history::path()?,
+            backups: crate::CONFIG.max_backups,
+        })
+    }
+}
+
+/// An exclusive advisory lock, released when dropped
+pub struct Lock(File);
+
+impl Lock {
+    /// Block until no other handlr process is modifying mimeapps.list
+    pub fn acquire_at(path: &Path) -> Result<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        Ok(Self(file))
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        unsafe { libc::flock(self.0.as_raw_fd(), libc::LOCK_UN) };
+    }
+}
+
+/// Replace a file's contents without ever leaving it truncated or half
+/// written: the new contents go to a temporary file next to it, which is
+/// then renamed over the original
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    // Write through symlinks (e.g. from a dotfiles repo) instead of replacing
+    // them
+    let path = &fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let tmp = {
+        let mut name = path.file_name().unwrap_or_default().to_owned();
+        name.push(format!(".{}.tmp", std::process::id()));
+        path.with_file_name(name)
+    };
+
+    let res = (|| {
+        let mut file = File::create(&tmp)?;
+        if let Ok(metadata) = fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    })();
+
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+
+    Ok(res?)
+}
+
+/// Copy a file to handlr's backup dir, keeping only the most recent `keep`
+/// backups of it
+pub fn backup(path: &Path, keep: usize) -> Result<()> {
+    if keep == 0 {
+        return Ok(());
+    }
+
+    let dir = xdg::BaseDirectories::with_prefix("handlr")?
+        .create_config_directory("backups")?;
+    backup_to(path, &dir, keep)
+}
+
+fn backup_to(path: &Path, dir: &Path, keep: usize) -> Result<()> {
+    if keep == 0 || !path.exists() {
+        return Ok(());
+    }
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+
+    fs::copy(path, dir.join(format!("{}.{}", name, timestamp)))?;
+
+    let mut backups = fs::read_dir(dir)?
+        .filter_map(|e| {
+            let path = e.ok()?.path();
+            let timestamp = path
+                .file_name()?
+                .to_str()?
+                .strip_prefix(name.as_ref())?
+                .strip_prefix('.')?
+                .parse::<u128>()
+                .ok()?;
+            Some((timestamp, path))
+        })
+        .collect::<Vec<(u128, PathBuf)>>();
+
+    backups.sort_unstable();
+    let excess = backups.len().saturating_sub(keep);
+
+    for (_, old) in backups.into_iter().take(excess) {
+        fs::remove_file(old)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn atomic_write() -> Result<()> {
+        let dir = TempDir::new("atomic");
+        let path = dir.join("mimeapps.list");
+
+        write_atomic(&path, b"[Default Applications]\n")?;
+        write_atomic(&path, b"[Added Associations]\n")?;
+
+        assert_eq!(fs::read_to_string(&path)?, "[Added Associations]\n");
+        assert_eq!(fs::read_dir(&dir)?.count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rotating_backups() -> Result<()> {
+        let dir = TempDir::new("backups");
+        let backups = dir.join("backups");
+        let path = dir.join("mimeapps.list");
+        fs::create_dir(&backups)?;
+
+        backup_to(&path, &backups, 3)?;
+        assert_eq!(fs::read_dir(&backups)?.count(), 0);
+
+        for i in 0..5 {
+            fs::write(&path, i.to_string())?;
+            backup_to(&path, &backups, 3)?;
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+
+        let mut kept = fs::read_dir(&backups)?
+            .map(|e| fs::read_to_string(e.unwrap().path()).unwrap())
+            .collect::<Vec<_>>();
+        kept.sort();
+        assert_eq!(kept, ["2", "3", "4"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn exclusive_lock() -> Result<()> {
+        let dir = TempDir::new("lock");
+        let path = dir.join("mimeapps.lock");
+
+        let lock = Lock::acquire_at(&path)?;
+        let other = fs::File::open(&path)?;
+        let res = unsafe {
+            libc::flock(other.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB)
+        };
+        assert_ne!(res, 0);
+
+        drop(lock);
+        let res = unsafe {
+            libc::flock(other.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB)
+        };
+        assert_eq!(res, 0);
+
+        Ok(())
+    }
+}
@@ -1,12 +1,17 @@
 use crate::{
-    common::{DesktopEntry, Handler},
+    apps::{index, Document},
+    common::{desktop_id, DesktopEntry, Handler},
     Result,
 };
 use mime::Mime;
 use std::{
-    collections::{HashMap, VecDeque},
-    convert::TryFrom,
+    collections::{HashMap, HashSet, VecDeque},
     ffi::OsString,
+    fs, iter,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 #[derive(Debug, Default, Clone)]
@@ -16,38 +21,208 @@ impl SystemApps {
     pub fn get_handlers(&self, mime: &Mime) -> Option<VecDeque<Handler>> {
         Some(self.0.get(mime)?.clone())
     }
-    pub fn get_handler(&self, mime: &Mime) -> Option<Handler> {
-        Some(self.get_handlers(mime)?.get(0).unwrap().clone())
-    }
 
     pub fn get_entries(
     ) -> Result<impl Iterator<Item = (OsString, DesktopEntry)>> {
-        Ok(xdg::BaseDirectories::new()?
-            .list_data_files_once("applications")
-            .into_iter()
-            .filter(|p| {
-                p.extension().map(|x| x.to_str()).flatten() == Some("desktop")
-            })
-            .filter_map(|p| {
-                Some((
-                    p.file_name().unwrap().to_owned(),
-                    DesktopEntry::try_from(p.clone()).ok()?,
-                ))
-            }))
+        Ok(index::entries()?.into_iter())
     }
 
     pub fn populate() -> Result<Self> {
+        // Only needed for dirs without an up to date mimeinfo.cache
+        let mut cached = None;
+        Self::from_dirs(desktop_id::applications_dirs()?, |dir| {
+            if cached.is_none() {
+                cached = Some(index::load()?);
+            }
+            Ok(cached.as_ref().unwrap().entries_in(dir))
+        })
+    }
+
+    /// Collect the handlers of every applications dir, highest precedence
+    /// first. A dir's mimeinfo.cache is used instead of its .desktop files
+    /// whenever it is up to date, otherwise they are parsed by `parse`.
+    fn from_dirs(
+        dirs: impl IntoIterator<Item = PathBuf>,
+        mut parse: impl FnMut(&Path) -> Result<Vec<DesktopEntry>>,
+    ) -> Result<Self> {
         let mut map = HashMap::<Mime, VecDeque<Handler>>::with_capacity(50);
+        // A .desktop file shadows those with the same name in later dirs
+        let mut seen = HashSet::<OsString>::new();
 
-        Self::get_entries()?.for_each(|(_, entry)| {
-            let (file_name, mimes) = (entry.file_name, entry.mimes);
-            mimes.into_iter().for_each(|mime| {
-                map.entry(mime)
-                    .or_default()
-                    .push_back(Handler::assume_valid(file_name.clone()));
-            });
-        });
+        for dir in dirs {
+            let files = desktop_id::list(&dir)
+                .into_iter()
+                .filter(|(name, _)| !seen.contains(name))
+                .collect::<Vec<_>>();
+            let names = files
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect::<HashSet<_>>();
+
+            let associations = match read_cache(&dir, &files) {
+                Some(associations) => associations,
+                None => parse(&dir)?
+                    .into_iter()
+                    .filter(|entry| !entry.hidden)
+                    .flat_map(|entry| {
+                        let name = entry.file_name;
+                        entry.mimes.into_iter().map(move |m| (m, name.clone()))
+                    })
+                    .collect(),
+            };
+
+            for (mime, name) in associations {
+                if names.contains(&name) {
+                    map.entry(mime)
+                        .or_default()
+                        .push_back(Handler::assume_valid(name));
+                }
+            }
+
+            seen.extend(names);
+        }
 
         Ok(Self(map))
     }
 }
+
+pub(super) fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).ok()?.modified().ok()
+}
+
+/// The associations listed in a dir's mimeinfo.cache, if it is at least as
+/// recent as the dir and all of its .desktop files
+fn read_cache(
+    dir: &Path,
+    files: &[(OsString, PathBuf)],
+) -> Option<Vec<(Mime, OsString)>> {
+    let path = dir.join("mimeinfo.cache");
+    // The cache is usually renamed into place after being written, which
+    // bumps the dir's mtime along with the cache's ctime
+    let cache_time = {
+        let metadata = fs::metadata(&path).ok()?;
+        let changed = UNIX_EPOCH
+            + Duration::new(
+                metadata.ctime() as u64,
+                metadata.ctime_nsec() as u32,
+            );
+        metadata.modified().ok()?.max(changed)
+    };
+
+    // Files added to a subdir only change the subdir's mtime
+    let stale = iter::once(dir)
+        .chain(files.iter().filter_map(|(_, p)| p.parent()))
+        .chain(files.iter().map(|(_, p)| p.as_path()))
+        .any(|p| modified(p).is_none_or(|time| time > cache_time));
+    if stale {
+        return None;
+    }
+
+    let document = fs::read_to_string(&path).ok()?.parse::<Document>().ok()?;
+
+    Some(
+        document
+            .properties("MIME Cache")
+            .filter_map(|(mime, handlers)| {
+                Some((Mime::from_str(mime).ok()?, handlers))
+            })
+            .flat_map(|(mime, handlers)| {
+                handlers
+                    .split(';')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(move |name| (mime.clone(), name.into()))
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn write(path: &Path, contents: &str) {
+        fs::write(path, contents).unwrap();
+    }
+
+    fn entry(name: &str, mimes: &str) -> String {
+        format!(
+            "[Desktop Entry]\nType=Application\nName={}\nExec={}\nMimeType={}\n",
+            name, name, mimes
+        )
+    }
+
+    fn handlers(apps: &SystemApps, mime: &str) -> Vec<String> {
+        apps.get_handlers(&Mime::from_str(mime).unwrap())
+            .into_iter()
+            .flatten()
+            .map(|h| h.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn shadowing() {
+        let dirs = vec![
+            PathBuf::from("tests/applications/user"),
+            PathBuf::from("tests/applications/system"),
+        ];
+        let apps = SystemApps::from_dirs(dirs, |dir| {
+            Ok(desktop_id::list(dir)
+                .into_iter()
+                .filter_map(|(id, path)| DesktopEntry::read(id, path).ok())
+                .collect())
+        })
+        .unwrap();
+
+        // foo.desktop is hidden by the user, bar.desktop overridden
+        assert!(handlers(&apps, "text/x-foo").is_empty());
+        assert_eq!(handlers(&apps, "text/x-bar"), ["bar.desktop"]);
+        assert_eq!(handlers(&apps, "text/x-baz"), ["baz.desktop"]);
+    }
+
+    #[test]
+    fn mimeinfo_cache() {
+        let root = TempDir::new("system");
+        let (user, system) = (root.join("user"), root.join("system"));
+        fs::create_dir_all(&user).unwrap();
+        fs::create_dir_all(&system).unwrap();
+
+        // Fresh: the cache is trusted over the .desktop files
+        write(&system.join("nvim.desktop"), &entry("nvim", "text/html;"));
+        write(&system.join("feh.desktop"), &entry("feh", "image/png;"));
+        write(
+            &system.join("mimeinfo.cache"),
+            "[MIME Cache]\ntext/html=nvim.desktop;\n\
+             image/png=feh.desktop;gone.desktop;\nimage/gif=feh.desktop;\n",
+        );
+
+        // Stale: nvim.desktop changed after the cache was written
+        write(
+            &user.join("mimeinfo.cache"),
+            "[MIME Cache]\ntext/plain=nvim.desktop;\n",
+        );
+        write(&user.join("nvim.desktop"), &entry("nvim", "text/x-c;"));
+        fs::File::options()
+            .write(true)
+            .open(user.join("nvim.desktop"))
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+
+        let apps = SystemApps::from_dirs(vec![user, system], |dir| {
+            Ok(desktop_id::list(dir)
+                .into_iter()
+                .filter_map(|(id, path)| DesktopEntry::read(id, path).ok())
+                .collect())
+        })
+        .unwrap();
+
+        assert_eq!(handlers(&apps, "text/x-c"), ["nvim.desktop"]);
+        assert!(handlers(&apps, "text/plain").is_empty());
+        // Shadowed by the user's nvim.desktop
+        assert!(handlers(&apps, "text/html").is_empty());
+        assert_eq!(handlers(&apps, "image/png"), ["feh.desktop"]);
+        assert_eq!(handlers(&apps, "image/gif"), ["feh.desktop"]);
+    }
+}
@@ -0,0 +1,196 @@
+use crate::common::{Handler, Unavailable};
+use mime::Mime;
+use std::{fmt, path::PathBuf};
+
+/// Every handler considered while resolving a mime, in the order they were
+/// looked at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    pub mime: Mime,
+    pub steps: Vec<Step>,
+    /// The handler picked, from the last step
+    pub handler: Option<Handler>,
+    /// Whether there was a choice for the selector to offer
+    pub selector: bool,
+}
+
+/// The handlers one source lists for one mime
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub mime: Mime,
+    pub relation: Relation,
+    pub source: Source,
+    pub candidates: Vec<Candidate>,
+}
+
+/// How the mime of a step relates to the one being resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relation {
+    Itself,
+    /// The mime being resolved is an alias of it
+    Alias,
+    /// The mime being resolved is a subclass of it
+    Ancestor,
+    Wildcard,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// A section of a mimeapps.list
+    File {
+        path: PathBuf,
+        section: &'static str,
+    },
+    /// The MimeType keys of .desktop files
+    SystemApps,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub handler: Handler,
+    pub rejected: Option<Rejection>,
+}
+
+/// Why a handler wasn't picked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    /// Its .desktop file can't be found
+    Dangling,
+    /// Listed in the Removed Associations of a mimeapps.list
+    Removed,
+    Unavailable(Unavailable),
+    /// NoDisplay entries aren't offered by the selector
+    NotDisplayed,
+}
+
+impl Trace {
+    pub fn new(mime: Mime) -> Self {
+        Self {
+            mime,
+            steps: Vec::new(),
+            handler: None,
+            selector: false,
+        }
+    }
+
+    /// The step the handler was picked in
+    pub fn matched(&self) -> Option<&Step> {
+        self.handler.as_ref().and(self.steps.last())
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        json::object! {
+            mime: self.mime.essence_str(),
+            handler: self.handler.as_ref().map(ToString::to_string),
+            matched: self.matched().map(|step| step.mime.essence_str()),
+            selector: self.selector,
+            steps: self.steps.iter().map(|step| json::object! {
+                mime: step.mime.essence_str(),
+                relation: step.relation.as_str(),
+                source: match &step.source {
+                    Source::File { section, .. } => *section,
+                    Source::SystemApps => "System Apps",
+                },
+                file: match &step.source {
+                    Source::File { path, .. } => {
+                        Some(path.to_string_lossy().into_owned())
+                    }
+                    Source::SystemApps => None,
+                },
+                candidates: step.candidates.iter().map(|c| json::object! {
+                    handler: c.handler.to_string(),
+                    rejected: c.rejected.as_ref().map(ToString::to_string),
+                }).collect::<Vec<_>>(),
+            }).collect::<Vec<_>>(),
+        }
+    }
+}
+
+impl Relation {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Itself => "itself",
+            Self::Alias => "alias",
+            Self::Ancestor => "subclass",
+            Self::Wildcard => "wildcard",
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Dangling => write!(f, ".desktop file not found"),
+            Self::Removed => write!(f, "removed association"),
+            Self::Unavailable(reason) => write!(f, "{}", reason),
+            Self::NotDisplayed => write!(f, "NoDisplay, not offered"),
+        }
+    }
+}
+
+/// A tree of the steps, grouped by mime
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.handler {
+            Some(handler) => writeln!(f, "{} → {}", self.mime, handler)?,
+            None => writeln!(f, "{} → no handler", self.mime)?,
+        }
+        if self.steps.is_empty() {
+            writeln!(f, "  nothing is associated with it or its ancestors")?;
+        }
+
+        let mut mime = None;
+        for (i, step) in self.steps.iter().enumerate() {
+            if mime != Some((&step.mime, step.relation)) {
+                mime = Some((&step.mime, step.relation));
+                match step.relation {
+                    Relation::Itself => writeln!(f, "  {}", step.mime)?,
+                    Relation::Alias => writeln!(
+                        f,
+                        "  {}, which {} is an alias of",
+                        step.mime, self.mime
+                    )?,
+                    Relation::Ancestor => writeln!(
+                        f,
+                        "  {}, which {} is a subclass of",
+                        step.mime, self.mime
+                    )?,
+                    Relation::Wildcard => {
+                        writeln!(f, "  {} (wildcard)", step.mime)?
+                    }
+                }
+            }
+
+            match &step.source {
+                Source::File { path, section } => {
+                    writeln!(f, "    {} in {}", section, path.display())?
+                }
+                Source::SystemApps => writeln!(f, "    System Apps")?,
+            }
+
+            let last = i + 1 == self.steps.len();
+            for candidate in &step.candidates {
+                match &candidate.rejected {
+                    Some(reason) => writeln!(
+                        f,
+                        "      ✗ {} ({})",
+                        candidate.handler, reason
+                    )?,
+                    None if last
+                        && self.handler.as_ref()
+                            == Some(&candidate.handler) =>
+                    {
+                        writeln!(f, "      ✓ {}", candidate.handler)?
+                    }
+                    None => writeln!(f, "      · {}", candidate.handler)?,
+                }
+            }
+        }
+
+        if self.selector {
+            writeln!(f, "  the selector offers the handlers marked ✓ and ·")?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,93 +1,384 @@
-use crate::{apps::SystemApps, common::Handler, Error, Result, CONFIG};
+use crate::{
+    apps::{
+        history,
+        storage::{self, Lock, Storage},
+        trace::{Candidate, Rejection, Relation, Source, Step, Trace},
+        Document, SystemApps,
+    },
+    common::{Handler, MIME_DB},
+    Error, Result, CONFIG,
+};
 use mime::Mime;
-use once_cell::sync::Lazy;
-use pest::Parser;
+use once_cell::sync::{Lazy, OnceCell};
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
+    ffi::OsString,
     io::Read,
+    iter,
     path::PathBuf,
     str::FromStr,
 };
 
 pub static APPS: Lazy<MimeApps> = Lazy::new(|| MimeApps::read().unwrap());
 
-#[derive(Debug, Default, Clone, pest_derive::Parser)]
-#[grammar = "common/ini.pest"]
+type Associations = HashMap<Mime, VecDeque<Handler>>;
+
+const ADDED_ASSOCIATIONS: &str = "Added Associations";
+const REMOVED_ASSOCIATIONS: &str = "Removed Associations";
+const DEFAULT_APPS: &str = "Default Applications";
+
+#[derive(Debug, Clone)]
 pub struct MimeApps {
-    added_associations: HashMap<Mime, VecDeque<Handler>>,
-    default_apps: HashMap<Mime, VecDeque<Handler>>,
-    system_apps: SystemApps,
+    /// Every mimeapps.list in the lookup chain, highest precedence first
+    files: Vec<MimeAppsFile>,
+    /// Position of the user's own mimeapps.list, the only one we write to
+    user: usize,
+    /// Whether a handler's .desktop file can be found right now. Only asked
+    /// of the handlers resolution gets to. Those that can't be found are
+    /// kept around when saving, but never picked.
+    exists: fn(&Handler) -> bool,
+    /// Only looked up once the mimeapps.list files have nothing to offer
+    system_apps: OnceCell<SystemApps>,
+}
+
+/// A single mimeapps.list from the lookup chain
+#[derive(Debug, Default, Clone)]
+struct MimeAppsFile {
+    path: PathBuf,
+    document: Document,
+    added_associations: Associations,
+    removed_associations: Associations,
+    default_apps: Associations,
+}
+
+/// No files, and every handler's .desktop file present
+#[cfg(test)]
+impl Default for MimeApps {
+    fn default() -> Self {
+        Self {
+            files: vec![MimeAppsFile::default()],
+            user: 0,
+            exists: |_| true,
+            system_apps: SystemApps::default().into(),
+        }
+    }
 }
 
 impl MimeApps {
+    fn user_file(&mut self) -> &mut MimeAppsFile {
+        &mut self.files[self.user]
+    }
+
     pub fn add_handler(&mut self, mime: Mime, handler: Handler) {
-        self.default_apps
+        self.user_file()
+            .default_apps
             .entry(mime)
             .or_default()
             .push_back(handler);
     }
 
     pub fn set_handler(&mut self, mime: Mime, handler: Handler) {
-        self.default_apps.insert(mime, vec![handler].into());
+        self.user_file()
+            .default_apps
+            .insert(mime, vec![handler].into());
     }
 
-    pub fn remove_handler(&mut self, mime: &Mime) -> Result<()> {
-        if let Some(_removed) = self.default_apps.remove(mime) {
-            self.save()?;
+    pub fn remove_handler(&mut self, mime: &Mime) {
+        self.user_file().default_apps.remove(mime);
+    }
+
+    /// Blacklist a handler for a mime, no matter where it was associated
+    pub fn remove_association(&mut self, mime: Mime, handler: Handler) {
+        let file = self.user_file();
+
+        if let Some(added) = file.added_associations.get_mut(&mime) {
+            added.retain(|h| h != &handler);
+            if added.is_empty() {
+                file.added_associations.remove(&mime);
+            }
         }
 
-        Ok(())
+        let removed = file.removed_associations.entry(mime).or_default();
+        if !removed.contains(&handler) {
+            removed.push_back(handler);
+        }
     }
 
     pub fn get_handler(&self, mime: &Mime) -> Result<Handler> {
-        self.get_handler_from_user(mime)
-            .or_else(|_| {
-                let wildcard =
-                    Mime::from_str(&format!("{}/*", mime.type_())).unwrap();
-                self.get_handler_from_user(&wildcard)
-            })
-            .or_else(|_| self.get_handler_from_added_associations(mime))
-    }
-
-    fn get_handler_from_user(&self, mime: &Mime) -> Result<Handler> {
-        match self.default_apps.get(mime) {
-            Some(handlers) if CONFIG.enable_selector && handlers.len() > 1 => {
-                let handlers = handlers
-                    .into_iter()
-                    .map(|h| (h, h.get_entry().unwrap().name))
-                    .collect::<Vec<_>>();
-
-                let handler = {
-                    let name =
-                        CONFIG.select(handlers.iter().map(|h| h.1.clone()))?;
-
-                    handlers
-                        .into_iter()
-                        .find(|h| h.1 == name)
-                        .unwrap()
-                        .0
-                        .clone()
+        self.trace(mime, true)?
+            .handler
+            .ok_or_else(|| Error::NotFound(mime.to_string()))
+    }
+
+    /// Resolve a mime, recording every handler considered on the way. The
+    /// selector is only run if `select` is set, otherwise the first handler
+    /// it would offer is picked.
+    pub fn trace(&self, mime: &Mime, select: bool) -> Result<Trace> {
+        let chain = MIME_DB
+            .ancestors(mime)
+            .into_iter()
+            .enumerate()
+            .map(|(i, ancestor)| {
+                let relation = match i {
+                    0 => Relation::Itself,
+                    _ if MIME_DB.unalias(mime) == Some(&ancestor) => {
+                        Relation::Alias
+                    }
+                    _ => Relation::Ancestor,
                 };
+                (ancestor, relation)
+            })
+            .collect::<Vec<_>>();
+
+        self.trace_chain(mime, &chain, select)
+    }
+
+    /// The user's defaults for any mime of the chain come before wildcards,
+    /// and those before associations
+    fn trace_chain(
+        &self,
+        mime: &Mime,
+        chain: &[(Mime, Relation)],
+        select: bool,
+    ) -> Result<Trace> {
+        use itertools::Itertools;
+
+        let mut trace = Trace::new(mime.clone());
+
+        let wildcards = chain.iter().map(|(mime, _)| {
+            let wildcard = format!("{}/*", mime.type_());
+            (Mime::from_str(&wildcard).unwrap(), Relation::Wildcard)
+        });
+        for (mime, relation) in chain
+            .iter()
+            .cloned()
+            .chain(wildcards)
+            .unique_by(|(mime, _)| mime.clone())
+        {
+            if self.trace_defaults(&mime, relation, select, &mut trace)? {
+                return Ok(trace);
+            }
+        }
+
+        for (mime, relation) in chain {
+            if self.trace_associations(mime, *relation, &mut trace)? {
+                return Ok(trace);
+            }
+        }
 
-                Ok(handler)
+        Ok(trace)
+    }
+
+    fn is_dangling(&self, handler: &Handler) -> bool {
+        !(self.exists)(handler)
+    }
+
+    /// Drop dangling handlers from all of the user's associations
+    pub fn prune(&mut self) {
+        let exists = self.exists;
+        let file = self.user_file();
+
+        for associations in file.sections_mut() {
+            associations
+                .values_mut()
+                .for_each(|handlers| handlers.retain(exists));
+            associations.retain(|_, handlers| !handlers.is_empty());
+        }
+    }
+
+    /// The first mimeapps.list with usable default handlers for the mime
+    /// decides. Returns whether a handler was picked.
+    fn trace_defaults(
+        &self,
+        mime: &Mime,
+        relation: Relation,
+        select: bool,
+        trace: &mut Trace,
+    ) -> Result<bool> {
+        for file in self.files.iter() {
+            let handlers = match file.default_apps.get(mime) {
+                Some(handlers) => handlers,
+                None => continue,
+            };
+
+            let mut candidates = handlers
+                .iter()
+                .map(|h| Candidate {
+                    handler: h.clone(),
+                    rejected: self
+                        .is_dangling(h)
+                        .then_some(Rejection::Dangling),
+                })
+                .collect::<Vec<_>>();
+            let usable = candidates.iter().filter(|c| c.rejected.is_none());
+
+            // Only offer what can be used here and is meant to be shown
+            let selector = CONFIG.enable_selector && usable.count() > 1;
+            if selector {
+                let desktops = crate::utils::current_desktops();
+                for candidate in
+                    candidates.iter_mut().filter(|c| c.rejected.is_none())
+                {
+                    candidate.rejected = match candidate.handler.get_entry() {
+                        Ok(entry) => entry
+                            .unavailable(&desktops)
+                            .map(Rejection::Unavailable)
+                            .or_else(|| {
+                                entry
+                                    .no_display
+                                    .then_some(Rejection::NotDisplayed)
+                            }),
+                        Err(_) => Some(Rejection::Dangling),
+                    };
+                }
             }
-            Some(handlers) => Ok(handlers.get(0).unwrap().clone()),
-            None => Err(Error::NotFound(mime.to_string())),
+
+            let choices = candidates
+                .iter()
+                .filter(|c| c.rejected.is_none())
+                .map(|c| c.handler.clone())
+                .collect::<Vec<_>>();
+            trace.steps.push(Step {
+                mime: mime.clone(),
+                relation,
+                source: Source::File {
+                    path: file.path.clone(),
+                    section: DEFAULT_APPS,
+                },
+                candidates,
+            });
+
+            match choices.len() {
+                // Nothing the selector could offer, so this mime has no
+                // default handler
+                0 if selector => return Ok(false),
+                // Every handler is dangling, try the next file
+                0 => continue,
+                1 => trace.handler = Some(choices[0].clone()),
+                _ if selector => {
+                    trace.selector = true;
+                    trace.handler = Some(match select {
+                        true => Self::select(&choices)?,
+                        false => choices[0].clone(),
+                    });
+                }
+                _ => trace.handler = Some(choices[0].clone()),
+            }
+            return Ok(true);
         }
+
+        Ok(false)
     }
 
-    fn get_handler_from_added_associations(
+    /// Let the user pick one of the handlers by name
+    fn select(handlers: &[Handler]) -> Result<Handler> {
+        let names = handlers
+            .iter()
+            .map(|h| Ok((h, h.get_entry()?.name)))
+            .collect::<Result<Vec<_>>>()?;
+        let name = CONFIG.select(names.iter().map(|(_, name)| name.clone()))?;
+
+        names
+            .into_iter()
+            .find(|(_, n)| *n == name)
+            .map(|(h, _)| h.clone())
+            .ok_or(Error::Cancelled)
+    }
+
+    /// The added associations of every mimeapps.list, then the system apps.
+    /// Returns whether a handler was picked.
+    fn trace_associations(
         &self,
         mime: &Mime,
-    ) -> Result<Handler> {
-        self.added_associations
-            .get(mime)
-            .map(|h| h.get(0).unwrap().clone())
-            .or_else(|| self.system_apps.get_handler(mime))
-            .ok_or(Error::NotFound(mime.to_string()))
+        relation: Relation,
+        trace: &mut Trace,
+    ) -> Result<bool> {
+        // Removed associations only apply to files of lower precedence and
+        // to the system apps
+        let mut removed = HashSet::new();
+
+        for file in self.files.iter() {
+            if let Some(added) = file.added_associations.get(mime) {
+                let source = Source::File {
+                    path: file.path.clone(),
+                    section: ADDED_ASSOCIATIONS,
+                };
+                let step = Step {
+                    mime: mime.clone(),
+                    relation,
+                    source,
+                    candidates: Vec::new(),
+                };
+                if self.trace_candidates(step, added, &removed, trace) {
+                    return Ok(true);
+                }
+            }
+
+            removed.extend(
+                file.removed_associations.get(mime).into_iter().flatten(),
+            );
+        }
+
+        let system = self.system_apps()?.get_handlers(mime).unwrap_or_default();
+        let step = Step {
+            mime: mime.clone(),
+            relation,
+            source: Source::SystemApps,
+            candidates: Vec::new(),
+        };
+        Ok(self.trace_candidates(step, &system, &removed, trace))
+    }
+
+    /// Pick the first of the handlers that isn't removed, dangling or
+    /// unavailable, recording those considered up to it
+    fn trace_candidates(
+        &self,
+        mut step: Step,
+        handlers: &VecDeque<Handler>,
+        removed: &HashSet<&Handler>,
+        trace: &mut Trace,
+    ) -> bool {
+        for handler in handlers {
+            let rejected = if removed.contains(handler) {
+                Some(Rejection::Removed)
+            } else if self.is_dangling(handler) {
+                Some(Rejection::Dangling)
+            } else {
+                handler.unavailable().map(Rejection::Unavailable)
+            };
+
+            let picked = rejected.is_none();
+            step.candidates.push(Candidate {
+                handler: handler.clone(),
+                rejected,
+            });
+            if picked {
+                trace.handler = Some(handler.clone());
+                break;
+            }
+        }
+
+        if !step.candidates.is_empty() {
+            trace.steps.push(step);
+        }
+        trace.handler.is_some()
     }
 
-    pub fn show_handler(&self, mime: &Mime, output_json: bool) -> Result<()> {
+    pub fn show_handler(
+        &self,
+        mime: &Mime,
+        output_json: bool,
+        explain: bool,
+    ) -> Result<()> {
+        if explain {
+            let trace = self.trace(mime, false)?;
+            match output_json {
+                true => println!("{}", trace.to_json()),
+                false => print!("{}", trace),
+            }
+            return Ok(());
+        }
+
         let handler = self.get_handler(mime)?;
         let output = if output_json {
             let entry = handler.get_entry()?;
@@ -96,6 +387,8 @@ impl MimeApps {
             (json::object! {
                 handler: handler.to_string(),
                 name: entry.name.as_str(),
+                generic_name: entry.generic_name.as_deref(),
+                comment: entry.comment.as_deref(),
                 cmd: cmd.0 + " " + &cmd.1.join(" "),
             })
             .to_string()
@@ -110,150 +403,490 @@ impl MimeApps {
         config.push("mimeapps.list");
         Ok(config)
     }
+    fn system_apps(&self) -> Result<&SystemApps> {
+        self.system_apps.get_or_try_init(SystemApps::populate)
+    }
     pub fn read() -> Result<Self> {
-        let raw_conf = {
-            let mut buf = String::new();
-            let exists = std::path::Path::new(&Self::path()?).exists();
-            std::fs::OpenOptions::new()
-                .write(!exists)
-                .create(!exists)
-                .read(true)
-                .open(Self::path()?)?
-                .read_to_string(&mut buf)?;
-            buf
-        };
-        let file = Self::parse(Rule::file, &raw_conf)?.next().unwrap();
+        let xdg = xdg::BaseDirectories::new()?;
+        let user_path = Self::path()?;
 
-        let mut current_section_name = "".to_string();
         let mut conf = Self {
-            added_associations: HashMap::default(),
-            default_apps: HashMap::default(),
-            system_apps: SystemApps::populate()?,
+            files: Vec::new(),
+            user: 0,
+            exists: Handler::exists,
+            system_apps: OnceCell::new(),
         };
 
-        file.into_inner().for_each(|line| {
-            match line.as_rule() {
-                Rule::section => {
-                    current_section_name = line.into_inner().concat();
-                }
-                Rule::property => {
-                    let mut inner_rules = line.into_inner(); // { name ~ "=" ~ value }
-
-                    let name = inner_rules.next().unwrap().as_str();
-                    let handlers = {
-                        use itertools::Itertools;
-
-                        inner_rules
-                            .next()
-                            .unwrap()
-                            .as_str()
-                            .split(";")
-                            .filter(|s| !s.is_empty())
-                            .unique()
-                            .filter_map(|s| Handler::from_str(s).ok())
-                            .collect::<VecDeque<_>>()
-                    };
-
-                    if !handlers.is_empty() {
-                        match (
-                            Mime::from_str(name),
-                            current_section_name.as_str(),
-                        ) {
-                            (Ok(mime), "Added Associations") => {
-                                conf.added_associations.insert(mime, handlers)
-                            }
-
-                            (Ok(mime), "Default Applications") => {
-                                conf.default_apps.insert(mime, handlers)
-                            }
-                            _ => None,
-                        };
-                    }
+        for path in lookup_chain(
+            xdg.get_config_home(),
+            xdg.get_config_dirs(),
+            xdg.get_data_home(),
+            xdg.get_data_dirs(),
+            &crate::utils::current_desktops(),
+        ) {
+            if path == user_path {
+                conf.user = conf.files.len();
+                conf.files.push(MimeAppsFile::read(path, true)?);
+            } else if path.exists() {
+                // A broken file we don't own shouldn't keep handlr from working
+                if let Ok(file) = MimeAppsFile::read(path, false) {
+                    conf.files.push(file);
                 }
-                _ => {}
             }
-        });
+        }
 
         Ok(conf)
     }
-    pub fn save(&self) -> Result<()> {
-        use itertools::Itertools;
-        use std::io::{prelude::*, BufWriter};
-
-        let f = std::fs::OpenOptions::new()
-            .read(true)
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(Self::path()?)?;
-        let mut writer = BufWriter::new(f);
-
-        writer.write_all(b"[Added Associations]\n")?;
-        for (k, v) in self.added_associations.iter().sorted() {
-            writer.write_all(k.essence_str().as_ref())?;
-            writer.write_all(b"=")?;
-            writer.write_all(v.iter().join(";").as_ref())?;
-            writer.write_all(b";\n")?;
+    /// Make changes to the user's mimeapps.list and save them, while holding
+    /// a lock so that concurrent handlr processes can't undo each other's work
+    pub fn update(&mut self, change: impl FnOnce(&mut Self)) -> Result<()> {
+        self.update_in(&Storage::new()?, change)
+    }
+    fn update_in(
+        &mut self,
+        storage: &Storage,
+        change: impl FnOnce(&mut Self),
+    ) -> Result<()> {
+        let _lock = Lock::acquire_at(&storage.lock)?;
+        self.reload_user_file()?;
+
+        let before = self.files[self.user].clone();
+        change(self);
+
+        // Recorded first, so that nothing gets saved that undo can't revert
+        let changes = self.files[self.user].changes_since(&before);
+        let recorded = !changes.is_empty();
+        if recorded {
+            history::record(&storage.history, history::Entry::new(changes))?;
+        }
+
+        if let Err(e) = self.files[self.user].save(storage.backups) {
+            if recorded {
+                let _ = history::forget(&storage.history, 1);
+            }
+            return Err(e);
         }
 
-        writer.write_all(b"\n[Default Applications]\n")?;
-        for (k, v) in self.default_apps.iter().sorted() {
-            writer.write_all(k.essence_str().as_ref())?;
-            writer.write_all(b"=")?;
-            writer.write_all(v.iter().join(";").as_ref())?;
-            writer.write_all(b";\n")?;
+        Ok(())
+    }
+    /// Revert the `count` most recent changes made through handlr. Changes
+    /// to mimes whose handlers were changed again by other means since are
+    /// left alone, and returned.
+    pub fn undo(&mut self, count: usize) -> Result<Vec<history::Change>> {
+        self.undo_in(&Storage::new()?, count)
+    }
+    fn undo_in(
+        &mut self,
+        storage: &Storage,
+        count: usize,
+    ) -> Result<Vec<history::Change>> {
+        let _lock = Lock::acquire_at(&storage.lock)?;
+        self.reload_user_file()?;
+
+        let entries = history::read(&storage.history)?;
+        let undone = &entries[entries.len().saturating_sub(count)..];
+        if undone.is_empty() {
+            return Err(Error::NothingToUndo);
         }
 
-        writer.flush()?;
+        let file = &mut self.files[self.user];
+        let mut conflicts = Vec::new();
+        for change in undone.iter().rev().flat_map(|e| e.changes.iter().rev()) {
+            let section = match file.section_mut(&change.section) {
+                Some(section) => section,
+                None => continue,
+            };
+
+            let current = section
+                .get(&change.mime)
+                .map(|h| h.iter().cloned().collect::<Vec<_>>())
+                .unwrap_or_default();
+            if current != change.new {
+                conflicts.push(change.clone());
+            } else if change.old.is_empty() {
+                section.remove(&change.mime);
+            } else {
+                section.insert(
+                    change.mime.clone(),
+                    change.old.iter().cloned().collect(),
+                );
+            }
+        }
+
+        file.save(storage.backups)?;
+        history::forget(&storage.history, undone.len())?;
+
+        Ok(conflicts)
+    }
+    /// Start over from what's on disk, it may have changed since we read it
+    fn reload_user_file(&mut self) -> Result<()> {
+        let path = self.files[self.user].path.clone();
+        self.files[self.user] = MimeAppsFile::read(path, true)?;
         Ok(())
     }
     pub fn print(&self, detailed: bool) -> Result<()> {
         use itertools::Itertools;
 
-        let to_rows = |map: &HashMap<Mime, VecDeque<Handler>>| {
-            map.iter()
-                .sorted()
+        fn to_rows<'a>(
+            map: impl Iterator<Item = (&'a Mime, &'a VecDeque<Handler>)>,
+        ) -> Vec<Vec<String>> {
+            map.sorted()
                 .map(|(k, v)| vec![k.to_string(), v.iter().join(", ")])
-                .collect::<Vec<_>>()
+                .collect()
+        }
+
+        // Merge the lookup chain so that each mime shows its effective handlers
+        let merged = |section: fn(&MimeAppsFile) -> &Associations| {
+            let mut merged = HashMap::new();
+            self.files.iter().flat_map(section).for_each(|(k, v)| {
+                merged.entry(k).or_insert(v);
+            });
+            merged
         };
 
         let table = ascii_table::AsciiTable::default();
+        let default_apps = merged(|f| &f.default_apps);
 
         if detailed {
+            let added_associations = merged(|f| &f.added_associations);
+            let removed_associations = merged(|f| &f.removed_associations);
+
             println!("Default Apps");
-            table.print(to_rows(&self.default_apps));
-            if !self.added_associations.is_empty() {
+            table.print(to_rows(default_apps.into_iter()));
+            if !added_associations.is_empty() {
                 println!("Added Associations");
-                table.print(to_rows(&self.added_associations));
+                table.print(to_rows(added_associations.into_iter()));
             }
+            if !removed_associations.is_empty() {
+                println!("Removed Associations");
+                table.print(to_rows(removed_associations.into_iter()));
+            }
+            // Point out the system apps that won't be picked, and why
+            let entries = SystemApps::get_entries()?.collect::<HashMap<_, _>>();
+            let desktops = crate::utils::current_desktops();
+            let describe = |handler: &Handler| {
+                let reason = entries
+                    .get(&OsString::from(handler.to_string()))
+                    .and_then(|entry| entry.unavailable(&desktops));
+                match reason {
+                    Some(reason) => format!("{} ({})", handler, reason),
+                    None => handler.to_string(),
+                }
+            };
+
             println!("System Apps");
-            table.print(to_rows(&self.system_apps.0));
+            let table = ascii_table::AsciiTable {
+                max_width: 120,
+                ..Default::default()
+            };
+            table.print(
+                self.system_apps()?
+                    .0
+                    .iter()
+                    .sorted()
+                    .map(|(mime, handlers)| {
+                        vec![
+                            mime.to_string(),
+                            handlers.iter().map(describe).join(", "),
+                        ]
+                    })
+                    .collect::<Vec<_>>(),
+            );
         } else {
-            table.print(to_rows(&self.default_apps));
+            table.print(to_rows(default_apps.into_iter()));
         }
 
         Ok(())
     }
+    pub fn print_dangling(&self) -> Result<()> {
+        use itertools::Itertools;
+
+        let rows = self
+            .files
+            .iter()
+            .flat_map(|f| {
+                f.entries().map(move |(section, mime, handler)| {
+                    (&f.path, section, mime, handler)
+                })
+            })
+            .filter(|(.., handler)| self.is_dangling(handler))
+            .sorted()
+            .map(|(path, section, mime, handler)| {
+                vec![
+                    mime.to_string(),
+                    handler.to_string(),
+                    section.to_owned(),
+                    path.display().to_string(),
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        ascii_table::AsciiTable::default().print(rows);
+
+        Ok(())
+    }
+    pub fn print_history() -> Result<()> {
+        use ascii_table::{AsciiTable, Column};
+        use itertools::Itertools;
+
+        let mut table = AsciiTable {
+            max_width: 120,
+            ..Default::default()
+        };
+        for (i, header) in ["#", "When", "Section", "Mime", "Before", "After"]
+            .iter()
+            .enumerate()
+        {
+            table.columns.insert(i, Column::with_header(header));
+        }
+
+        let entries = history::read(&history::path()?)?;
+        if entries.is_empty() {
+            println!("No changes made through handlr yet");
+            return Ok(());
+        }
+
+        let rows = entries
+            .iter()
+            .rev()
+            .enumerate()
+            .flat_map(|(i, entry)| {
+                entry.changes.iter().map(move |change| {
+                    vec![
+                        (i + 1).to_string(),
+                        entry.age(),
+                        change.section.clone(),
+                        change.mime.to_string(),
+                        change.old.iter().join(", "),
+                        change.new.iter().join(", "),
+                    ]
+                })
+            })
+            .collect::<Vec<_>>();
+
+        table.print(rows);
+
+        Ok(())
+    }
     pub fn list_handlers() -> Result<()> {
         use std::{io::Write, os::unix::ffi::OsStrExt};
 
         let stdout = std::io::stdout();
         let mut stdout = stdout.lock();
 
-        SystemApps::get_entries()?.for_each(|(_, e)| {
-            stdout.write_all(e.file_name.as_bytes()).unwrap();
+        SystemApps::get_entries()?.for_each(|(id, e)| {
+            stdout.write_all(id.as_bytes()).unwrap();
             stdout.write_all(b"\t").unwrap();
             stdout.write_all(e.name.as_bytes()).unwrap();
             stdout.write_all(b"\n").unwrap();
+
+            for action in &e.actions {
+                stdout.write_all(id.as_bytes()).unwrap();
+                writeln!(stdout, ":{}\t{}: {}", action.id, e.name, action.name)
+                    .unwrap();
+            }
         });
 
         Ok(())
     }
 }
 
+impl MimeAppsFile {
+    fn read(path: PathBuf, create: bool) -> Result<Self> {
+        let raw_conf = {
+            let mut buf = String::new();
+            let exists = path.exists();
+            std::fs::OpenOptions::new()
+                .write(create && !exists)
+                .create(create && !exists)
+                .read(true)
+                .open(&path)?
+                .read_to_string(&mut buf)?;
+            buf
+        };
+        let document = raw_conf.parse::<Document>()?;
+
+        let section = |name: &str| {
+            document
+                .properties(name)
+                .filter_map(|(mime, handlers)| {
+                    Some((Mime::from_str(mime).ok()?, parse_handlers(handlers)))
+                })
+                .filter(|(_, handlers)| !handlers.is_empty())
+                .collect::<Associations>()
+        };
+
+        Ok(Self {
+            added_associations: section(ADDED_ASSOCIATIONS),
+            removed_associations: section(REMOVED_ASSOCIATIONS),
+            default_apps: section(DEFAULT_APPS),
+            path,
+            document,
+        })
+    }
+    fn sections(&self) -> Vec<(&'static str, &Associations)> {
+        vec![
+            (ADDED_ASSOCIATIONS, &self.added_associations),
+            (REMOVED_ASSOCIATIONS, &self.removed_associations),
+            (DEFAULT_APPS, &self.default_apps),
+        ]
+    }
+    fn sections_mut(&mut self) -> [&mut Associations; 3] {
+        [
+            &mut self.added_associations,
+            &mut self.removed_associations,
+            &mut self.default_apps,
+        ]
+    }
+    fn section_mut(&mut self, name: &str) -> Option<&mut Associations> {
+        match name {
+            ADDED_ASSOCIATIONS => Some(&mut self.added_associations),
+            REMOVED_ASSOCIATIONS => Some(&mut self.removed_associations),
+            DEFAULT_APPS => Some(&mut self.default_apps),
+            _ => None,
+        }
+    }
+    /// Every mime whose handlers differ from an earlier version of the file
+    fn changes_since(&self, before: &Self) -> Vec<history::Change> {
+        use itertools::Itertools;
+
+        let handlers = |associations: &Associations, mime: &Mime| {
+            associations
+                .get(mime)
+                .map(|h| h.iter().cloned().collect::<Vec<_>>())
+                .unwrap_or_default()
+        };
+
+        self.sections()
+            .into_iter()
+            .zip(before.sections())
+            .flat_map(|((section, now), (_, then))| {
+                now.keys().chain(then.keys()).unique().sorted().filter_map(
+                    move |mime| {
+                        let (old, new) =
+                            (handlers(then, mime), handlers(now, mime));
+                        (old != new).then(|| history::Change {
+                            section: section.to_owned(),
+                            mime: mime.clone(),
+                            old,
+                            new,
+                        })
+                    },
+                )
+            })
+            .collect()
+    }
+    /// Every handler in the file, along with where it was found
+    fn entries(&self) -> impl Iterator<Item = (&'static str, &Mime, &Handler)> {
+        self.sections()
+            .into_iter()
+            .flat_map(|(section, associations)| {
+                associations.iter().flat_map(move |(mime, handlers)| {
+                    handlers.iter().map(move |h| (section, mime, h))
+                })
+            })
+    }
+    /// Write the file back if anything changed, backing up the old version
+    fn save(&self, backups: usize) -> Result<()> {
+        use itertools::Itertools;
+
+        let mut document = self.document.clone();
+
+        for (section, associations) in self.sections() {
+            let existing = document
+                .properties(section)
+                .filter_map(|(key, handlers)| {
+                    Some((
+                        key.to_owned(),
+                        Mime::from_str(key).ok()?,
+                        parse_handlers(handlers),
+                    ))
+                })
+                .collect::<Vec<_>>();
+
+            // Only lines whose meaning changed are rewritten, everything else
+            // is written back as it was read
+            for (key, mime, handlers) in existing.iter() {
+                match associations.get(mime) {
+                    Some(new) if new != handlers => {
+                        document.set(section, key, &join_handlers(new))
+                    }
+                    None if !handlers.is_empty() => {
+                        document.remove(section, key)
+                    }
+                    _ => {}
+                }
+            }
+
+            for (mime, handlers) in associations.iter().sorted() {
+                if !existing.iter().any(|(_, m, _)| m == mime) {
+                    document.set(
+                        section,
+                        mime.essence_str(),
+                        &join_handlers(handlers),
+                    );
+                }
+            }
+        }
+
+        if document == self.document && self.path.exists() {
+            return Ok(());
+        }
+
+        storage::backup(&self.path, backups)?;
+        storage::write_atomic(&self.path, document.to_string().as_bytes())
+    }
+}
+
+fn parse_handlers(value: &str) -> VecDeque<Handler> {
+    use itertools::Itertools;
+
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .unique()
+        .map(|s| Handler::assume_valid(s.into()))
+        .collect()
+}
+
+fn join_handlers(handlers: &VecDeque<Handler>) -> String {
+    use itertools::Itertools;
+
+    format!("{};", handlers.iter().join(";"))
+}
+
+/// Locations of mimeapps.list in order of precedence, as laid out in the
+/// [spec](https://specifications.freedesktop.org/mime-apps-spec/mime-apps-spec-latest.html#file)
+fn lookup_chain(
+    config_home: PathBuf,
+    config_dirs: Vec<PathBuf>,
+    data_home: PathBuf,
+    data_dirs: Vec<PathBuf>,
+    desktops: &[String],
+) -> Vec<PathBuf> {
+    let data = iter::once(data_home)
+        .chain(data_dirs)
+        .map(|dir| dir.join("applications"));
+
+    iter::once(config_home)
+        .chain(config_dirs)
+        .chain(data)
+        .flat_map(|dir| {
+            desktops
+                .iter()
+                .map(|d| format!("{}-mimeapps.list", d.to_lowercase()))
+                .chain(iter::once("mimeapps.list".to_owned()))
+                .map(|name| dir.join(name))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::{handler, TempDir};
 
     #[test]
     fn wildcard_mimes() -> Result<()> {
@@ -289,4 +922,364 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn inherited_handlers() -> Result<()> {
+        // The handler picked and the mime it was found for
+        let resolve = |apps: &MimeApps, mimes: &[&str]| {
+            let chain = mimes
+                .iter()
+                .enumerate()
+                .map(|(i, m)| {
+                    let relation = match i {
+                        0 => Relation::Itself,
+                        _ => Relation::Ancestor,
+                    };
+                    (Mime::from_str(m).unwrap(), relation)
+                })
+                .collect::<Vec<_>>();
+            let trace = apps.trace_chain(&chain[0].0, &chain, false).unwrap();
+            let matched = trace.matched().map(|step| step.mime.to_string());
+            (trace.handler.map(|h| h.to_string()), matched)
+        };
+        let found = |handler: &str, mime: &str| {
+            (Some(handler.to_owned()), Some(mime.to_owned()))
+        };
+
+        let mut apps = MimeApps::default();
+        apps.set_handler(
+            Mime::from_str("text/plain")?,
+            handler("nvim.desktop"),
+        );
+        apps.set_handler(Mime::from_str("text/*")?, handler("less.desktop"));
+        apps.set_handler(
+            Mime::from_str("application/pdf")?,
+            handler("okular.desktop"),
+        );
+
+        // An ancestor's default beats a wildcard
+        assert_eq!(
+            resolve(&apps, &["text/rust", "text/plain"]),
+            found("nvim.desktop", "text/plain")
+        );
+        assert_eq!(
+            resolve(&apps, &["text/x-foo"]),
+            found("less.desktop", "text/*")
+        );
+        assert_eq!(
+            resolve(&apps, &["application/x-pdf", "application/pdf"]),
+            found("okular.desktop", "application/pdf")
+        );
+        assert_eq!(resolve(&apps, &["image/png"]), (None, None));
+
+        Ok(())
+    }
+
+    #[test]
+    fn trace() -> Result<()> {
+        let png = Mime::from_str("image/png")?;
+
+        let mut apps = MimeApps::default();
+        apps.files.push(MimeAppsFile {
+            path: "/usr/share/applications/mimeapps.list".into(),
+            ..Default::default()
+        });
+        apps.files[0].path = "/home/u/.config/mimeapps.list".into();
+        apps.set_handler(png.clone(), handler("gone.desktop"));
+        apps.exists = |h| h.to_string() != "gone.desktop";
+        apps.user_file()
+            .removed_associations
+            .insert(png.clone(), vec![handler("feh.desktop")].into());
+        apps.files[1].added_associations.insert(
+            png.clone(),
+            vec![handler("feh.desktop"), handler("sxiv.desktop")].into(),
+        );
+
+        let trace =
+            apps.trace_chain(&png, &[(png.clone(), Relation::Itself)], false)?;
+        let candidate = |name: &str, rejected| Candidate {
+            handler: handler(name),
+            rejected,
+        };
+        assert_eq!(
+            trace.steps,
+            [
+                Step {
+                    mime: png.clone(),
+                    relation: Relation::Itself,
+                    source: Source::File {
+                        path: "/home/u/.config/mimeapps.list".into(),
+                        section: DEFAULT_APPS,
+                    },
+                    candidates: vec![candidate(
+                        "gone.desktop",
+                        Some(Rejection::Dangling)
+                    )],
+                },
+                // The user removed feh.desktop from the system's associations
+                Step {
+                    mime: png.clone(),
+                    relation: Relation::Itself,
+                    source: Source::File {
+                        path: "/usr/share/applications/mimeapps.list".into(),
+                        section: ADDED_ASSOCIATIONS,
+                    },
+                    candidates: vec![
+                        candidate("feh.desktop", Some(Rejection::Removed)),
+                        candidate("sxiv.desktop", None),
+                    ],
+                },
+            ]
+        );
+        assert_eq!(trace.handler, Some(handler("sxiv.desktop")));
+        assert!(!trace.selector);
+
+        Ok(())
+    }
+
+    #[test]
+    fn lookup_chain_order() {
+        let chain = lookup_chain(
+            "/home/u/.config".into(),
+            vec!["/etc/xdg".into()],
+            "/home/u/.local/share".into(),
+            vec!["/usr/share".into()],
+            &["GNOME".into()],
+        );
+
+        assert_eq!(
+            chain,
+            [
+                "/home/u/.config/gnome-mimeapps.list",
+                "/home/u/.config/mimeapps.list",
+                "/etc/xdg/gnome-mimeapps.list",
+                "/etc/xdg/mimeapps.list",
+                "/home/u/.local/share/applications/gnome-mimeapps.list",
+                "/home/u/.local/share/applications/mimeapps.list",
+                "/usr/share/applications/gnome-mimeapps.list",
+                "/usr/share/applications/mimeapps.list",
+            ]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn chain_precedence() -> Result<()> {
+        let pdf = Mime::from_str("application/pdf")?;
+        let png = Mime::from_str("image/png")?;
+
+        let mut system = MimeAppsFile::default();
+        system
+            .default_apps
+            .insert(pdf.clone(), vec![handler("evince.desktop")].into());
+        system
+            .default_apps
+            .insert(png.clone(), vec![handler("eog.desktop")].into());
+
+        let mut apps = MimeApps {
+            files: vec![MimeAppsFile::default(), system],
+            ..Default::default()
+        };
+        apps.set_handler(pdf.clone(), handler("zathura.desktop"));
+
+        assert_eq!(apps.get_handler(&pdf)?.to_string(), "zathura.desktop");
+        assert_eq!(apps.get_handler(&png)?.to_string(), "eog.desktop");
+
+        Ok(())
+    }
+
+    #[test]
+    fn removed_associations() -> Result<()> {
+        let text = Mime::from_str("text/plain")?;
+
+        let mut system = MimeAppsFile::default();
+        system.added_associations.insert(
+            text.clone(),
+            vec![handler("wine-notepad.desktop"), handler("gedit.desktop")]
+                .into(),
+        );
+
+        let mut apps = MimeApps {
+            files: vec![MimeAppsFile::default(), system],
+            ..Default::default()
+        };
+        apps.system_apps.get_mut().unwrap().0.insert(
+            text.clone(),
+            vec![handler("wine-wordpad.desktop"), handler("kate.desktop")]
+                .into(),
+        );
+
+        apps.remove_association(text.clone(), handler("wine-notepad.desktop"));
+        assert_eq!(apps.get_handler(&text)?.to_string(), "gedit.desktop");
+
+        apps.remove_association(text.clone(), handler("gedit.desktop"));
+        apps.remove_association(text.clone(), handler("wine-wordpad.desktop"));
+        assert_eq!(apps.get_handler(&text)?.to_string(), "kate.desktop");
+
+        Ok(())
+    }
+
+    #[test]
+    fn undo() -> Result<()> {
+        let pdf = Mime::from_str("application/pdf")?;
+        let png = Mime::from_str("image/png")?;
+
+        let dir = TempDir::new("undo");
+        let storage = Storage {
+            lock: dir.join("mimeapps.lock"),
+            history: dir.join("history.jsonl"),
+            backups: 0,
+        };
+        let mut apps = MimeApps::default();
+        apps.files[0].path = dir.join("mimeapps.list");
+        let defaults = |apps: &MimeApps, mime: &Mime| {
+            apps.files[0]
+                .default_apps
+                .get(mime)
+                .map(|h| h.iter().map(ToString::to_string).collect::<Vec<_>>())
+        };
+
+        apps.update_in(&storage, |apps| {
+            apps.set_handler(pdf.clone(), handler("evince.desktop"))
+        })?;
+        apps.update_in(&storage, |apps| {
+            apps.set_handler(pdf.clone(), handler("zathura.desktop"))
+        })?;
+        assert_eq!(history::read(&storage.history)?.len(), 2);
+
+        assert!(apps.undo_in(&storage, 1)?.is_empty());
+        assert_eq!(defaults(&apps, &pdf).unwrap(), ["evince.desktop"]);
+        // Going past the start of history undoes what there is
+        assert!(apps.undo_in(&storage, 5)?.is_empty());
+        assert_eq!(defaults(&apps, &pdf), None);
+        assert!(matches!(
+            apps.undo_in(&storage, 1),
+            Err(Error::NothingToUndo)
+        ));
+
+        // Another program changes one of the mimes between set and undo
+        apps.update_in(&storage, |apps| {
+            apps.set_handler(pdf.clone(), handler("evince.desktop"));
+            apps.set_handler(png.clone(), handler("feh.desktop"));
+        })?;
+        std::fs::write(
+            &apps.files[0].path,
+            "[Default Applications]\napplication/pdf=okular.desktop;\nimage/png=feh.desktop;\n",
+        )?;
+        let conflicts = apps.undo_in(&storage, 1)?;
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].mime, pdf);
+        assert_eq!(
+            std::fs::read_to_string(&apps.files[0].path)?,
+            "[Default Applications]\napplication/pdf=okular.desktop;\n"
+        );
+
+        // Nothing is saved if the change can't be recorded
+        std::fs::remove_file(&storage.history)?;
+        std::fs::create_dir(&storage.history)?;
+        apps.update_in(&storage, |apps| {
+            apps.set_handler(pdf.clone(), handler("zathura.desktop"))
+        })
+        .unwrap_err();
+        assert_eq!(
+            std::fs::read_to_string(&apps.files[0].path)?,
+            "[Default Applications]\napplication/pdf=okular.desktop;\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn dangling_handlers() -> Result<()> {
+        let pdf = Mime::from_str("application/pdf")?;
+        let png = Mime::from_str("image/png")?;
+
+        let mut apps = MimeApps::default();
+        apps.add_handler(pdf.clone(), handler("unmounted.desktop"));
+        apps.add_handler(pdf.clone(), handler("zathura.desktop"));
+        apps.add_handler(png.clone(), handler("unmounted.desktop"));
+        apps.exists = |h| h.to_string() != "unmounted.desktop";
+
+        assert_eq!(apps.get_handler(&pdf)?.to_string(), "zathura.desktop");
+        apps.get_handler(&png).unwrap_err();
+
+        apps.prune();
+        assert_eq!(
+            apps.files[0].default_apps.get(&pdf),
+            Some(&vec![handler("zathura.desktop")].into())
+        );
+        assert_eq!(apps.files[0].default_apps.get(&png), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn changes() -> Result<()> {
+        let pdf = Mime::from_str("application/pdf")?;
+        let text = Mime::from_str("text/plain")?;
+
+        let mut apps = MimeApps::default();
+        apps.set_handler(pdf.clone(), handler("evince.desktop"));
+        apps.set_handler(text.clone(), handler("nvim.desktop"));
+        let before = apps.files[0].clone();
+
+        apps.add_handler(pdf.clone(), handler("zathura.desktop"));
+        apps.remove_handler(&text);
+        apps.remove_association(text.clone(), handler("wine.desktop"));
+
+        assert_eq!(
+            apps.files[0].changes_since(&before),
+            [
+                history::Change {
+                    section: REMOVED_ASSOCIATIONS.into(),
+                    mime: text.clone(),
+                    old: vec![],
+                    new: vec![handler("wine.desktop")],
+                },
+                history::Change {
+                    section: DEFAULT_APPS.into(),
+                    mime: pdf,
+                    old: vec![handler("evince.desktop")],
+                    new: vec![
+                        handler("evince.desktop"),
+                        handler("zathura.desktop")
+                    ],
+                },
+                history::Change {
+                    section: DEFAULT_APPS.into(),
+                    mime: text,
+                    old: vec![handler("nvim.desktop")],
+                    new: vec![],
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn save_is_lossless() -> Result<()> {
+        let raw = std::fs::read_to_string("tests/mimeapps/kde.list")?;
+        let dir = TempDir::new("lossless");
+        let path = dir.join("mimeapps.list");
+        std::fs::write(&path, &raw)?;
+
+        let mut file = MimeAppsFile::read(path.clone(), false)?;
+        file.save(0)?;
+        assert_eq!(std::fs::read_to_string(&path)?, raw);
+
+        file.default_apps.insert(
+            Mime::from_str("text/html")?,
+            vec![handler("firefox.desktop")].into(),
+        );
+        file.save(0)?;
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            format!("{}text/html=firefox.desktop;\n", raw)
+        );
+
+        Ok(())
+    }
 }
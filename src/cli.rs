@@ -1,4 +1,7 @@
-use crate::common::{Handler, MimeOrExtension, UserPath};
+use crate::{
+    common::{Handler, MimeOrExtension, UserPath},
+    config::Detect,
+};
 
 #[derive(clap::Clap)]
 #[clap(global_setting = clap::AppSettings::DeriveDisplayOrder)]
@@ -9,10 +12,31 @@ pub enum Cmd {
     List {
         #[clap(long, short)]
         all: bool,
+        /// Only list handlers whose .desktop file can't be found
+        #[clap(long)]
+        dangling: bool,
     },
 
+    /// Remove handlers whose .desktop file can't be found from mimeapps.list
+    Prune,
+
     /// Open a path/URL with its default handler
     Open {
+        /// Run one of the handler's desktop actions instead
+        #[clap(long)]
+        action: Option<String>,
+        /// Show how the handler of each path would be found, but don't open
+        /// anything
+        #[clap(long)]
+        dry_run: bool,
+        /// Show the --dry-run traces as JSON, one line per path
+        #[clap(long, requires = "dry-run")]
+        json: bool,
+        /// What to detect the mime of files from, instead of the `detection`
+        /// setting
+        #[clap(long, possible_values = &["extension", "content", "combined"])]
+        detect: Option<Detect>,
+        /// `-` opens what is piped to handlr, once it exits
         #[clap(required = true)]
         paths: Vec<UserPath>,
     },
@@ -28,6 +52,9 @@ pub enum Cmd {
 
     /// Launch the handler for specified extension/mime with optional arguments
     Launch {
+        /// Run one of the handler's desktop actions instead
+        #[clap(long)]
+        action: Option<String>,
         mime: MimeOrExtension,
         args: Vec<UserPath>,
     },
@@ -36,9 +63,34 @@ pub enum Cmd {
     Get {
         #[clap(long)]
         json: bool,
+        /// Show every handler considered on the way, and why those that
+        /// weren't picked were passed over
+        #[clap(long)]
+        explain: bool,
         mime: MimeOrExtension,
     },
 
+    /// Show why this mime/extension opens with its handler, same as
+    /// `get --explain`
+    Why {
+        #[clap(long)]
+        json: bool,
+        mime: MimeOrExtension,
+    },
+
+    /// Show the mime of each path/URL
+    Mime {
+        /// Also show what it was detected from, one JSON line per path
+        #[clap(long)]
+        json: bool,
+        /// What to detect the mime of files from, instead of the `detection`
+        /// setting
+        #[clap(long, possible_values = &["extension", "content", "combined"])]
+        detect: Option<Detect>,
+        #[clap(required = true)]
+        paths: Vec<UserPath>,
+    },
+
     /// Add a handler for given mime/extension
     /// Note that the first handler is the default
     Add {
@@ -46,6 +98,29 @@ pub enum Cmd {
         handler: Handler,
     },
 
+    /// Stop associating a handler with given mime/extension
+    /// Hides it from handlers registered by other mimeapps.list files and .desktop entries
+    RemoveAssociation {
+        mime: MimeOrExtension,
+        handler: Handler,
+    },
+
+    /// Manage handlr's cache of parsed .desktop files
+    Cache {
+        #[clap(subcommand)]
+        cmd: CacheCmd,
+    },
+
+    /// Show changes made to mimeapps.list, most recent first
+    History,
+
+    /// Revert the most recent changes made to mimeapps.list
+    Undo {
+        /// How many changes to revert
+        #[clap(default_value = "1")]
+        count: usize,
+    },
+
     #[clap(setting = clap::AppSettings::Hidden)]
     Autocomplete {
         #[clap(short)]
@@ -54,3 +129,9 @@ pub enum Cmd {
         mimes: bool,
     },
 }
+
+#[derive(clap::Clap)]
+pub enum CacheCmd {
+    /// Parse all .desktop files again
+    Rebuild,
+}
@@ -1,23 +1,92 @@
-use crate::{Error, Result};
-use aho_corasick::AhoCorasick;
+use crate::{
+    common::{
+        exec::{self, Arity},
+        Spool,
+    },
+    config::WorkingDir,
+    Error, Result,
+};
+use freedesktop_entry_parser::AttrSelector;
 use mime::Mime;
 use std::{
     collections::HashMap,
     convert::TryFrom,
     ffi::OsString,
     path::{Path, PathBuf},
-    process::{Command, Stdio},
+    process::{Child, Command, Stdio},
     str::FromStr,
 };
 
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct DesktopEntry {
+    /// Localized, as are the generic name and comment
     pub(crate) name: String,
+    pub(crate) generic_name: Option<String>,
+    pub(crate) comment: Option<String>,
     pub(crate) exec: String,
+    pub(crate) icon: Option<String>,
+    /// The desktop file ID, which is the file name unless the file is in a
+    /// subdir of an applications dir
     pub(crate) file_name: OsString,
+    /// Where the entry was read from
+    pub(crate) path: PathBuf,
+    /// The dir to run the program in, from the Path key
+    pub(crate) working_dir: Option<PathBuf>,
     pub(crate) terminal: bool,
     pub(crate) mimes: Vec<Mime>,
     pub(crate) categories: HashMap<String, ()>,
+    /// Program that must be installed for the entry to be usable
+    pub(crate) try_exec: Option<String>,
+    /// The entry counts as deleted
+    pub(crate) hidden: bool,
+    /// The entry shouldn't be shown in menus, but may still open files
+    pub(crate) no_display: bool,
+    pub(crate) only_show_in: Vec<String>,
+    pub(crate) not_show_in: Vec<String>,
+    pub(crate) actions: Vec<Action>,
+    /// The action being run instead of the entry's own Exec
+    pub(crate) action: Option<String>,
+    /// Where a Type=Link entry points to
+    pub(crate) url: Option<String>,
+    /// Launched through `org.freedesktop.Application` on the session bus
+    /// rather than by running Exec
+    pub(crate) dbus_activatable: bool,
+}
+
+/// An alternative way to launch an entry, from a `[Desktop Action]` group
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Action {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) exec: String,
+    pub(crate) icon: Option<String>,
+}
+
+/// Why an existing entry can't be used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Unavailable {
+    Hidden,
+    /// The program named by TryExec can't be found
+    NotInstalled(String),
+    /// Excluded from the current desktop by OnlyShowIn or NotShowIn
+    NotShownIn(Vec<String>),
+}
+
+impl std::fmt::Display for Unavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Hidden => write!(f, "hidden"),
+            Self::NotInstalled(program) => {
+                write!(f, "{} not installed", program)
+            }
+            Self::NotShownIn(desktops) if desktops.is_empty() => {
+                write!(f, "desktop specific")
+            }
+            Self::NotShownIn(desktops) => {
+                write!(f, "not shown in {}", desktops.join(":"))
+            }
+        }
+    }
 }
 
 #[derive(PartialEq, Eq, Copy, Clone)]
@@ -27,98 +96,276 @@ pub enum Mode {
 }
 
 impl DesktopEntry {
-    pub fn exec(&self, mode: Mode, arguments: Vec<String>) -> Result<()> {
-        let supports_multiple =
-            self.exec.contains("%F") || self.exec.contains("%U");
-        if arguments.is_empty() {
-            self.exec_inner(vec![])?
-        } else if supports_multiple || mode == Mode::Launch {
-            self.exec_inner(arguments)?;
-        } else {
-            for arg in arguments {
-                self.exec_inner(vec![arg])?;
+    /// Whether the entry may be used on the given desktops, following
+    /// Hidden, TryExec, OnlyShowIn and NotShowIn
+    pub fn unavailable(&self, desktops: &[String]) -> Option<Unavailable> {
+        if self.hidden {
+            return Some(Unavailable::Hidden);
+        }
+
+        if let Some(program) = &self.try_exec {
+            if !is_installed(program) {
+                return Some(Unavailable::NotInstalled(program.clone()));
+            }
+        }
+
+        let shown = |list: &[String]| desktops.iter().any(|d| list.contains(d));
+        if (!self.only_show_in.is_empty() && !shown(&self.only_show_in))
+            || shown(&self.not_show_in)
+        {
+            return Some(Unavailable::NotShownIn(desktops.to_vec()));
+        }
+
+        None
+    }
+    /// The entry, running the given action instead of its own Exec
+    pub fn with_action(mut self, id: &str) -> Result<Self> {
+        let action = match self.actions.iter().find(|a| a.id == id) {
+            Some(action) => action.clone(),
+            None => {
+                return Err(Error::NoAction(
+                    self.file_name.to_string_lossy().into(),
+                    id.into(),
+                ))
             }
         };
 
-        Ok(())
+        self.name = action.name;
+        self.exec = action.exec;
+        self.icon = action.icon.or(self.icon);
+        self.action = Some(action.id);
+        Ok(self)
     }
-    fn exec_inner(&self, args: Vec<String>) -> Result<()> {
+    /// The processes left running in the background, which is none when the
+    /// program ran in the terminal or was activated over D-Bus, and the
+    /// files downloaded for them
+    pub fn exec(
+        &self,
+        mode: Mode,
+        arguments: Vec<String>,
+    ) -> Result<(Vec<Child>, Vec<Spool>)> {
+        #[cfg(feature = "dbus")]
+        if self.dbus_activatable {
+            // Exec is only a fallback for when D-Bus activation fails
+            match zbus::blocking::Connection::session()
+                .map_err(Error::from)
+                .and_then(|bus| self.activate(&bus, arguments.clone()))
+            {
+                Err(_) if !self.exec.is_empty() => {}
+                result => return result.map(|()| (Vec::new(), Vec::new())),
+            }
+        }
+
+        let arity = Arity::of(&exec::split(&self.exec)?);
+        let (arguments, downloads) = match mode {
+            Mode::Open => {
+                let fetcher = crate::CONFIG.fetcher();
+                arity.convert(arguments, fetcher.as_ref().map(|f| f as _))?
+            }
+            Mode::Launch => (arguments, Vec::new()),
+        };
+
+        // Launched programs are given the arguments as they are
+        let children = match arity {
+            Arity::Single(_) if mode == Mode::Open && arguments.len() > 1 => {
+                arguments
+                    .into_iter()
+                    .filter_map(|arg| self.exec_inner(vec![arg]).transpose())
+                    .collect::<Result<_>>()?
+            }
+            _ => self.exec_inner(arguments)?.into_iter().collect(),
+        };
+        Ok((children, downloads))
+    }
+    fn exec_inner(&self, args: Vec<String>) -> Result<Option<Child>> {
+        let working_dir = self.working_dir(crate::CONFIG.working_dir, &args);
         let mut cmd = {
             let (cmd, args) = self.get_cmd(args)?;
             let mut cmd = Command::new(cmd);
             cmd.args(args);
             cmd
         };
+        if let Some(dir) = working_dir {
+            cmd.current_dir(dir);
+        }
 
         if self.terminal && atty::is(atty::Stream::Stdout) {
             cmd.spawn()?.wait()?;
+            Ok(None)
         } else {
-            cmd.stdout(Stdio::null()).stderr(Stdio::null()).spawn()?;
+            Ok(Some(
+                cmd.stdout(Stdio::null()).stderr(Stdio::null()).spawn()?,
+            ))
         }
+    }
+    /// Call the entry's `org.freedesktop.Application` methods on a bus, the
+    /// arguments being opened as URIs
+    #[cfg(feature = "dbus")]
+    fn activate(
+        &self,
+        bus: &zbus::blocking::Connection,
+        arguments: Vec<String>,
+    ) -> Result<()> {
+        use zbus::zvariant::Value;
+
+        // The app ID is the desktop file ID without the suffix, and its
+        // object path is derived from it
+        let id = self.file_name.to_string_lossy();
+        let id = id.strip_suffix(".desktop").unwrap_or(&id);
+        let path = format!("/{}", id.replace('.', "/").replace('-', "_"));
+
+        let proxy = zbus::blocking::Proxy::new(
+            bus,
+            id,
+            path,
+            "org.freedesktop.Application",
+        )?;
+        let platform_data = HashMap::<&str, Value>::new();
+        let (uris, _) =
+            Arity::Multiple(exec::Kind::Url).convert(arguments, None)?;
+
+        match &self.action {
+            Some(action) => proxy.call_method(
+                "ActivateAction",
+                &(action, Vec::<Value>::new(), platform_data),
+            )?,
+            None if uris.is_empty() => {
+                proxy.call_method("Activate", &(platform_data,))?
+            }
+            None => proxy.call_method("Open", &(uris, platform_data))?,
+        };
 
         Ok(())
     }
+    /// Where to run the program: the Path key if there is one, otherwise
+    /// wherever the policy says. None means this process' working dir.
+    fn working_dir(
+        &self,
+        policy: WorkingDir,
+        args: &[String],
+    ) -> Option<PathBuf> {
+        if let Some(dir) = &self.working_dir {
+            return Some(dir.clone());
+        }
+
+        match policy {
+            WorkingDir::Inherit => None,
+            WorkingDir::Home => std::env::var_os("HOME").map(PathBuf::from),
+            WorkingDir::Parent => {
+                // Arguments are converted to file:// URLs for some handlers
+                let arg = args.first()?;
+                let path = match url::Url::parse(arg) {
+                    Ok(url) if url.scheme() == "file" => {
+                        url.to_file_path().ok()?
+                    }
+                    _ => PathBuf::from(arg),
+                };
+                Some(path.canonicalize().ok()?.parent()?.to_owned())
+            }
+        }
+    }
     pub fn get_cmd(&self, args: Vec<String>) -> Result<(String, Vec<String>)> {
-        let special =
-            AhoCorasick::new_auto_configured(&["%f", "%F", "%u", "%U"]);
-
-        let mut exec = shlex::split(&self.exec).unwrap();
-
-        // The desktop entry doesn't contain arguments - we make best effort and append them at
-        // the end
-        if special.is_match(&self.exec) {
-            exec = exec
-                .into_iter()
-                .flat_map(|s| match s.as_str() {
-                    "%f" | "%F" | "%u" | "%U" => args.clone(),
-                    s if special.is_match(s) => vec![{
-                        let mut replaced =
-                            String::with_capacity(s.len() + args.len() * 2);
-                        special.replace_all_with(
-                            s,
-                            &mut replaced,
-                            |_, _, dst| {
-                                dst.push_str(args.clone().join(" ").as_str());
-                                false
-                            },
-                        );
-                        replaced
-                    }],
-                    _ => vec![s],
-                })
-                .collect()
-        } else {
-            exec.extend_from_slice(&args);
+        let mut exec = exec::expand(exec::split(&self.exec)?, self, &args);
+        if exec.is_empty() {
+            return Err(Error::BadExec(self.exec.clone(), "no program"));
         }
 
         // If the entry expects a terminal (emulator), but this process is not running in one, we
         // launch a new one.
         if self.terminal && !atty::is(atty::Stream::Stdout) {
-            exec = shlex::split(&crate::config::Config::terminal()?)
-                .unwrap()
-                .into_iter()
-                .chain(vec!["-e".to_owned()])
-                .chain(exec)
-                .collect();
+            exec = crate::config::Config::terminal()?.run_in(exec)?;
         }
 
         Ok((exec.remove(0), exec))
     }
+    /// The command line of this terminal emulator running another one
+    fn run_in(&self, exec: Vec<String>) -> Result<Vec<String>> {
+        Ok(exec::expand(exec::split(&self.exec)?, self, &[])
+            .into_iter()
+            .chain(vec!["-e".to_owned()])
+            .chain(exec)
+            .collect())
+    }
 }
 
-fn parse_file(path: &Path) -> Option<DesktopEntry> {
-    let raw_entry = freedesktop_entry_parser::parse_entry(&path).ok()?;
+/// Whether a program is an executable file, looked up in `$PATH` unless it is
+/// a path itself
+fn is_installed(program: &str) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    let executable = |path: &Path| {
+        path.metadata()
+            .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+    };
+
+    if program.contains('/') {
+        return executable(Path::new(program));
+    }
+
+    std::env::var_os("PATH").is_some_and(|paths| {
+        std::env::split_paths(&paths).any(|dir| executable(&dir.join(program)))
+    })
+}
+
+/// Keys to look up localized values by, best match first, following the
+/// spec's `lang_COUNTRY@MODIFIER` fallback rules
+fn locale_keys(locale: &str) -> Vec<String> {
+    let (rest, modifier) = match locale.split_once('@') {
+        Some((rest, modifier)) => (rest, Some(modifier)),
+        None => (locale, None),
+    };
+    // The encoding is ignored
+    let rest = rest.split('.').next().unwrap_or_default();
+    let (lang, country) = match rest.split_once('_') {
+        Some((lang, country)) => (lang, Some(country)),
+        None => (rest, None),
+    };
+
+    let mut keys = Vec::new();
+    if let (Some(country), Some(modifier)) = (country, modifier) {
+        keys.push(format!("{}_{}@{}", lang, country, modifier));
+    }
+    if let Some(country) = country {
+        keys.push(format!("{}_{}", lang, country));
+    }
+    if let Some(modifier) = modifier {
+        keys.push(format!("{}@{}", lang, modifier));
+    }
+    keys.push(lang.to_owned());
+    keys
+}
+
+/// The value of a key in the best matching locale, or the unlocalized one
+fn localized<T: AsRef<str>>(
+    section: &AttrSelector<T>,
+    key: &str,
+    locales: &[String],
+) -> Option<String> {
+    locales
+        .iter()
+        .find_map(|locale| section.attr_with_param(key, locale))
+        .or_else(|| section.attr(key))
+        .map(str::to_owned)
+}
+
+fn parse_file(path: &Path, locales: &[String]) -> Option<DesktopEntry> {
+    let raw_entry = freedesktop_entry_parser::parse_entry(path).ok()?;
     let section = raw_entry.section("Desktop Entry");
 
-    let mut entry = DesktopEntry::default();
-    entry.file_name = path.file_name()?.to_owned();
+    let mut actions = Vec::new();
+    let mut entry = DesktopEntry {
+        name: localized(&section, "Name", locales).unwrap_or_default(),
+        generic_name: localized(&section, "GenericName", locales),
+        comment: localized(&section, "Comment", locales),
+        file_name: path.file_name()?.to_owned(),
+        path: path.to_owned(),
+        ..Default::default()
+    };
 
-    for attr in section.attrs().into_iter().filter(|a| a.has_value()) {
+    for attr in section.attrs().filter(|a| a.has_value()) {
         match attr.name {
-            "Name" if entry.name == "" => {
-                entry.name = attr.value.unwrap().into();
-            }
             "Exec" => entry.exec = attr.value.unwrap().into(),
+            "Icon" => entry.icon = Some(attr.value.unwrap().into()),
             "MimeType" => {
                 entry.mimes = attr
                     .value
@@ -128,6 +375,18 @@ fn parse_file(path: &Path) -> Option<DesktopEntry> {
                     .collect::<Vec<_>>();
             }
             "Terminal" => entry.terminal = attr.value.unwrap() == "true",
+            "DBusActivatable" => {
+                entry.dbus_activatable = attr.value.unwrap() == "true"
+            }
+            "Path" => entry.working_dir = Some(attr.value.unwrap().into()),
+            "TryExec" => entry.try_exec = Some(attr.value.unwrap().into()),
+            "Hidden" => entry.hidden = attr.value.unwrap() == "true",
+            "NoDisplay" => entry.no_display = attr.value.unwrap() == "true",
+            "OnlyShowIn" => {
+                entry.only_show_in = split_list(attr.value.unwrap())
+            }
+            "NotShowIn" => entry.not_show_in = split_list(attr.value.unwrap()),
+            "Actions" => actions = split_list(attr.value.unwrap()),
             "Categories" => {
                 entry.categories = attr
                     .value
@@ -141,27 +400,419 @@ fn parse_file(path: &Path) -> Option<DesktopEntry> {
         }
     }
 
-    if !entry.name.is_empty() && !entry.exec.is_empty() {
+    // Only the actions listed in Actions are valid
+    entry.actions = actions
+        .into_iter()
+        .filter_map(|id| {
+            let section = raw_entry.section(format!("Desktop Action {}", id));
+            Some(Action {
+                name: localized(&section, "Name", locales)?,
+                // Actions of D-Bus activatable entries needn't have one
+                exec: match section.attr("Exec") {
+                    Some(exec) => exec.into(),
+                    None if entry.dbus_activatable => String::new(),
+                    None => return None,
+                },
+                icon: section.attr("Icon").map(Into::into),
+                id,
+            })
+        })
+        .collect();
+
+    if section.attr("Type") == Some("Link") {
+        entry.url = section.attr("URL").map(Into::into);
+    }
+
+    // Hidden entries needn't have anything else
+    let launchable =
+        !entry.exec.is_empty() || entry.dbus_activatable || entry.url.is_some();
+    if entry.hidden || (!entry.name.is_empty() && launchable) {
         Some(entry)
     } else {
         None
     }
 }
 
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(';')
+        .filter(|s| !s.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+impl DesktopEntry {
+    /// Read the file backing a desktop file ID
+    pub fn read(id: OsString, path: PathBuf) -> Result<Self> {
+        Ok(Self {
+            file_name: id,
+            ..Self::try_from(path)?
+        })
+    }
+    /// Read a Windows `.url` shortcut as the Link entry it amounts to
+    pub fn read_shortcut(path: PathBuf) -> Result<Self> {
+        let url = freedesktop_entry_parser::parse_entry(&path)
+            .ok()
+            .and_then(|raw| {
+                let url = raw.section("InternetShortcut").attr("URL")?.trim();
+                (!url.is_empty()).then(|| url.to_owned())
+            })
+            .ok_or_else(|| Error::BadEntry(path.clone()))?;
+
+        Ok(Self {
+            name: path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into(),
+            file_name: path.file_name().unwrap_or_default().to_owned(),
+            url: Some(url),
+            path,
+            ..Default::default()
+        })
+    }
+}
+
 impl TryFrom<PathBuf> for DesktopEntry {
     type Error = Error;
     fn try_from(path: PathBuf) -> Result<DesktopEntry> {
-        parse_file(&path).ok_or(Error::BadEntry(path))
+        let locales = crate::utils::messages_locale()
+            .map(|locale| locale_keys(&locale))
+            .unwrap_or_default();
+        parse_file(&path, &locales).ok_or(Error::BadEntry(path))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::TempDir;
+    #[cfg(feature = "dbus")]
+    use zbus::zvariant::OwnedValue;
+
+    #[test]
+    fn availability() {
+        let desktops = |list: &[&str]| {
+            list.iter().map(|d| d.to_string()).collect::<Vec<_>>()
+        };
+        let gnome = parse_str("OnlyShowIn=GNOME;Unity;\n");
+        assert_eq!(gnome.unavailable(&desktops(&["ubuntu", "GNOME"])), None);
+        assert_eq!(
+            gnome.unavailable(&desktops(&["sway"])),
+            Some(Unavailable::NotShownIn(desktops(&["sway"])))
+        );
+        assert!(gnome.unavailable(&[]).is_some());
+
+        let not_kde = parse_str("NotShowIn=KDE;\n");
+        assert_eq!(not_kde.unavailable(&[]), None);
+        assert!(not_kde.unavailable(&desktops(&["KDE"])).is_some());
+
+        let hidden = parse_str("Hidden=true\n");
+        assert_eq!(hidden.unavailable(&[]), Some(Unavailable::Hidden));
+
+        let missing = parse_str("TryExec=handlr-test-missing-program\n");
+        assert_eq!(
+            missing.unavailable(&[]),
+            Some(Unavailable::NotInstalled(
+                "handlr-test-missing-program".into()
+            ))
+        );
+        assert_eq!(parse_str("TryExec=sh\n").unavailable(&[]), None);
+        assert!(parse_str("TryExec=/nonexistent/sh\n")
+            .unavailable(&[])
+            .is_some());
+    }
+
+    /// Parse an entry with the given keys added to a valid one
+    fn parse_str(keys: &str) -> DesktopEntry {
+        let dir = TempDir::new("entry");
+        let path = dir.join("test.desktop");
+        std::fs::write(
+            &path,
+            format!("[Desktop Entry]\nName=Test\nExec=test\n{}", keys),
+        )
+        .unwrap();
+
+        parse_file(&path, &[]).unwrap()
+    }
+
+    #[test]
+    fn actions() -> Result<()> {
+        let entry = parse_file(
+            Path::new("tests/applications/system/kde/okular.desktop"),
+            &[],
+        )
+        .unwrap();
+        // Groups that aren't listed in Actions are ignored
+        assert_eq!(
+            entry
+                .actions
+                .iter()
+                .map(|a| a.id.as_str())
+                .collect::<Vec<_>>(),
+            ["presentation"]
+        );
+
+        let presentation = entry.clone().with_action("presentation")?;
+        assert_eq!(presentation.name, "Present");
+        assert_eq!(presentation.exec, "okular --presentation %U");
+        assert_eq!(presentation.icon, entry.icon);
+
+        assert!(matches!(
+            entry.with_action("unlisted"),
+            Err(Error::NoAction(..))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn localization() {
+        assert_eq!(
+            locale_keys("sr_YU.UTF-8@Latn"),
+            ["sr_YU@Latn", "sr_YU", "sr@Latn", "sr"]
+        );
+        assert_eq!(locale_keys("de_DE.UTF-8"), ["de_DE", "de"]);
+        assert_eq!(locale_keys("ja"), ["ja"]);
+
+        let path =
+            Path::new("tests/applications/system/gnome-calculator.desktop");
+        let entry =
+            |locale: &str| parse_file(path, &locale_keys(locale)).unwrap();
+
+        let german = entry("de_AT.UTF-8");
+        assert_eq!(german.name, "Taschenrechner");
+        assert_eq!(german.generic_name.as_deref(), Some("Rechner"));
+        // Only the unlocalized comment exists
+        assert_eq!(german.comment.as_deref(), Some("Perform calculations"));
+
+        assert_eq!(entry("sr_RS@latin").name, "Kalkulator");
+        assert_eq!(entry("sr_RS").name, "Калкулатор");
+        assert_eq!(entry("ja_JP.UTF-8").name, "電卓");
+        assert_eq!(entry("fr_FR").name, "Calculator");
+        assert_eq!(parse_file(path, &[]).unwrap().name, "Calculator");
+    }
+
+    #[test]
+    fn working_dir() {
+        let args = vec!["tests/cmus.desktop".to_owned()];
+        let tests = Path::new("tests").canonicalize().unwrap();
+        let entry = parse_str("");
+
+        assert_eq!(entry.working_dir(WorkingDir::Inherit, &args), None);
+        assert_eq!(
+            entry.working_dir(WorkingDir::Parent, &args),
+            Some(tests.clone())
+        );
+        let url = url::Url::from_file_path(tests.join("cmus.desktop"))
+            .unwrap()
+            .to_string();
+        assert_eq!(entry.working_dir(WorkingDir::Parent, &[url]), Some(tests));
+        // Nothing to take the dir of
+        assert_eq!(entry.working_dir(WorkingDir::Parent, &[]), None);
+        assert_eq!(
+            entry.working_dir(
+                WorkingDir::Parent,
+                &["https://example.com/a.txt".into()]
+            ),
+            None
+        );
+
+        let game = parse_str("Path=/opt/game\n");
+        assert_eq!(
+            game.working_dir(WorkingDir::Parent, &args),
+            Some(PathBuf::from("/opt/game"))
+        );
+    }
+
+    /// Records the calls made to it
+    #[cfg(feature = "dbus")]
+    struct Application(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+    #[cfg(feature = "dbus")]
+    #[zbus::dbus_interface(name = "org.freedesktop.Application")]
+    impl Application {
+        fn activate(&self, _platform_data: HashMap<String, OwnedValue>) {
+            self.0.lock().unwrap().push("Activate".into());
+        }
+        fn open(
+            &self,
+            uris: Vec<String>,
+            _platform_data: HashMap<String, OwnedValue>,
+        ) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("Open {}", uris.join(" ")));
+        }
+        fn activate_action(
+            &self,
+            action: String,
+            _parameter: Vec<OwnedValue>,
+            _platform_data: HashMap<String, OwnedValue>,
+        ) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("ActivateAction {}", action));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "dbus")]
+    #[ignore = "needs dbus-daemon"]
+    fn dbus_activation() -> Result<()> {
+        use std::io::BufRead;
+        use zbus::blocking::ConnectionBuilder;
+
+        // A private session bus
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address=1"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let mut address = String::new();
+        std::io::BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)?;
+        let address = address.trim();
+
+        let calls = std::sync::Arc::default();
+        let _app = ConnectionBuilder::address(address)?
+            .name("org.example.Test_App")?
+            .serve_at(
+                "/org/example/Test_App",
+                Application(std::sync::Arc::clone(&calls)),
+            )?
+            .build()?;
+        let bus = ConnectionBuilder::address(address)?.build()?;
+
+        let entry = DesktopEntry {
+            file_name: "org.example.Test_App.desktop".into(),
+            dbus_activatable: true,
+            actions: vec![Action {
+                id: "new-window".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        entry.activate(&bus, vec![])?;
+        entry.activate(
+            &bus,
+            vec!["/tmp/a b.txt".into(), "https://example.com/".into()],
+        )?;
+        entry
+            .clone()
+            .with_action("new-window")?
+            .activate(&bus, vec![])?;
+
+        // Nothing answers for this one, so Exec would be run instead
+        let missing = DesktopEntry {
+            file_name: "org.example.Missing.desktop".into(),
+            ..entry
+        };
+        assert!(missing.activate(&bus, vec![]).is_err());
+
+        daemon.kill()?;
+        daemon.wait()?;
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "Activate",
+                "Open file:///tmp/a%20b.txt https://example.com/",
+                "ActivateAction new-window",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn exec_arguments() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new("exec");
+        let script = dir.join("record");
+        std::fs::write(&script, "#!/bin/sh\necho \"$@\" >> \"$0.calls\"\n")?;
+        std::fs::set_permissions(
+            &script,
+            std::fs::Permissions::from_mode(0o755),
+        )?;
+        let calls = || std::fs::read_to_string(dir.join("record.calls"));
+
+        let entry = DesktopEntry {
+            exec: format!("{} %f", script.display()),
+            ..Default::default()
+        };
+        let args = || vec!["/tmp/a.txt".to_owned(), "/tmp/b.txt".to_owned()];
+
+        // A file at a time for a key that takes one
+        for mut child in entry.exec(Mode::Open, args())?.0 {
+            child.wait()?;
+        }
+        let mut opened =
+            calls()?.lines().map(str::to_owned).collect::<Vec<_>>();
+        opened.sort();
+        assert_eq!(opened, ["/tmp/a.txt", "/tmp/b.txt"]);
+
+        std::fs::remove_file(dir.join("record.calls"))?;
+        for mut child in entry.exec(Mode::Launch, args())?.0 {
+            child.wait()?;
+        }
+        assert_eq!(calls()?, "/tmp/a.txt /tmp/b.txt\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn terminal() -> Result<()> {
+        let terminal = DesktopEntry {
+            exec: r#"kitty --title "a terminal" %U"#.into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            terminal.run_in(vec!["htop".into()])?,
+            ["kitty", "--title", "a terminal", "-e", "htop"]
+        );
+
+        let broken = DesktopEntry {
+            exec: r#"kitty --title "a terminal"#.into(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            broken.run_in(vec!["htop".into()]),
+            Err(Error::BadExec(..))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn links() {
+        let link = parse_file(Path::new("tests/link.desktop"), &[]).unwrap();
+        assert_eq!(link.url.as_deref(), Some("https://example.com/"));
+        assert_eq!(link.exec, "");
+
+        let shortcut =
+            DesktopEntry::read_shortcut("tests/shortcut.url".into()).unwrap();
+        assert_eq!(
+            shortcut.url.as_deref(),
+            Some("https://example.com/docs?page=1")
+        );
+        assert_eq!(shortcut.name, "shortcut");
+        assert!(
+            DesktopEntry::read_shortcut("tests/link.desktop".into()).is_err()
+        );
+
+        // Only Link entries have a URL, and it doesn't make up for Exec
+        assert_eq!(parse_str("URL=https://example.com/\n").url, None);
+        assert_eq!(
+            parse_file(Path::new("tests/applications/user/foo.desktop"), &[])
+                .unwrap()
+                .url,
+            None
+        );
+    }
 
     #[test]
     fn complex_exec() {
-        let entry = parse_file(Path::new("tests/cmus.desktop")).unwrap();
+        let entry = parse_file(Path::new("tests/cmus.desktop"), &[]).unwrap();
         assert_eq!(entry.mimes.len(), 2);
         assert_eq!(entry.mimes[0].essence_str(), "audio/mp3");
         assert_eq!(entry.mimes[1].essence_str(), "audio/ogg");
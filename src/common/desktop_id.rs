@@ -0,0 +1,159 @@
+//! Desktop file IDs and the .desktop files backing them. Every lookup goes
+//! through here, so that all of handlr agrees on which file an ID refers to.
+
+use crate::Result;
+use std::{
+    ffi::{OsStr, OsString},
+    fs, iter,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+/// Every applications dir, highest precedence first
+pub fn applications_dirs() -> Result<Vec<PathBuf>> {
+    let xdg = xdg::BaseDirectories::new()?;
+    Ok(iter::once(xdg.get_data_home())
+        .chain(xdg.get_data_dirs())
+        .map(|dir| dir.join("applications"))
+        .collect())
+}
+
+/// Every .desktop file in an applications dir or its subdirs with its ID,
+/// sorted by ID. The ID is the path relative to the applications dir, with
+/// `/` replaced by `-`: `kde/okular.desktop` becomes `kde-okular.desktop`.
+pub fn list(dir: &Path) -> Vec<(OsString, PathBuf)> {
+    let mut files = Vec::new();
+    list_into(dir, OsStr::new(""), &mut files);
+
+    // Of two files with the same ID, the one closest to the top wins
+    files.sort_by_key(|(id, path)| (id.clone(), path.components().count()));
+    files.dedup_by(|a, b| a.0 == b.0);
+    files
+}
+
+fn list_into(dir: &Path, prefix: &OsStr, files: &mut Vec<(OsString, PathBuf)>) {
+    for path in fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok().map(|e| e.path()))
+    {
+        let name = match path.file_name() {
+            Some(name) => name.to_owned(),
+            None => continue,
+        };
+
+        if path.is_dir() {
+            let mut prefix = prefix.to_owned();
+            prefix.push(&name);
+            prefix.push("-");
+            list_into(&path, &prefix, files);
+        } else if path.extension().and_then(|x| x.to_str()) == Some("desktop") {
+            let mut id = prefix.to_owned();
+            id.push(&name);
+            files.push((id, path));
+        }
+    }
+}
+
+/// The file backing an ID: the one in the applications dir of highest
+/// precedence, even if it is Hidden and thus shadows the others
+pub fn find(id: &OsStr) -> Result<Option<PathBuf>> {
+    Ok(find_in(&applications_dirs()?, id))
+}
+
+fn find_in(dirs: &[PathBuf], id: &OsStr) -> Option<PathBuf> {
+    dirs.iter().find_map(|dir| find_in_dir(dir, id.as_bytes()))
+}
+
+/// Any `-` in the ID may stand for a subdir, so try each one that exists
+fn find_in_dir(dir: &Path, id: &[u8]) -> Option<PathBuf> {
+    let path = dir.join(OsStr::from_bytes(id));
+    if path.is_file() {
+        return Some(path);
+    }
+
+    id.iter()
+        .enumerate()
+        .filter(|(_, &b)| b == b'-')
+        .find_map(|(i, _)| {
+            let subdir = dir.join(OsStr::from_bytes(&id[..i]));
+            match subdir.is_dir() {
+                true => find_in_dir(&subdir, &id[i + 1..]),
+                false => None,
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> Vec<PathBuf> {
+        vec![
+            PathBuf::from("tests/applications/user"),
+            PathBuf::from("tests/applications/system"),
+        ]
+    }
+
+    #[test]
+    fn precedence() {
+        let dirs = fixture();
+        let find = |id: &str| find_in(&dirs, OsStr::new(id));
+
+        assert_eq!(
+            find("foo.desktop"),
+            Some(PathBuf::from("tests/applications/user/foo.desktop"))
+        );
+        assert_eq!(
+            find("bar.desktop"),
+            Some(PathBuf::from("tests/applications/user/bar.desktop"))
+        );
+        assert_eq!(
+            find("baz.desktop"),
+            Some(PathBuf::from("tests/applications/system/baz.desktop"))
+        );
+        assert_eq!(find("qux.desktop"), None);
+    }
+
+    #[test]
+    fn subdirs() {
+        let dirs = fixture();
+        let find = |id: &str| find_in(&dirs, OsStr::new(id));
+
+        assert_eq!(
+            list(&dirs[1])
+                .into_iter()
+                .map(|(id, _)| id.into_string().unwrap())
+                .collect::<Vec<_>>(),
+            [
+                "bar.desktop",
+                "baz.desktop",
+                "foo.desktop",
+                "gnome-calculator.desktop",
+                "kde-apps-dolphin.desktop",
+                "kde-okular.desktop",
+            ]
+        );
+
+        assert_eq!(
+            find("kde-okular.desktop"),
+            Some(PathBuf::from(
+                "tests/applications/system/kde/okular.desktop"
+            ))
+        );
+        assert_eq!(
+            find("kde-apps-dolphin.desktop"),
+            Some(PathBuf::from(
+                "tests/applications/system/kde/apps/dolphin.desktop"
+            ))
+        );
+        assert_eq!(
+            find("gnome-calculator.desktop"),
+            Some(PathBuf::from(
+                "tests/applications/system/gnome-calculator.desktop"
+            ))
+        );
+        assert_eq!(find("kde-missing.desktop"), None);
+        assert_eq!(find("okular.desktop"), None);
+    }
+}
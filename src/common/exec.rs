@@ -0,0 +1,431 @@
+use crate::{
+    common::{fetch, DesktopEntry, Fetch, Spool, UserPath},
+    Error, Result,
+};
+
+/// Field codes that are replaced by the files or URLs being opened
+const FILE_CODES: &[char] = &['f', 'F', 'u', 'U'];
+
+/// Field codes the spec deprecated, they expand to nothing
+const DEPRECATED_CODES: &[char] = &['d', 'D', 'n', 'N', 'v', 'm'];
+
+/// Whether an Exec key takes local files or URLs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    File,
+    Url,
+}
+
+/// How many files or URLs a single process of an Exec key takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    /// The Exec key has no field code for them, they are appended as is
+    None,
+    Single(Kind),
+    Multiple(Kind),
+}
+
+impl Arity {
+    /// Derived from the first file or URL field code of a split Exec key
+    pub fn of(exec: &[String]) -> Self {
+        exec.iter()
+            .flat_map(|arg| field_codes(arg))
+            .find_map(|code| match code {
+                'f' => Some(Self::Single(Kind::File)),
+                'F' => Some(Self::Multiple(Kind::File)),
+                'u' => Some(Self::Single(Kind::Url)),
+                'U' => Some(Self::Multiple(Kind::Url)),
+                _ => None,
+            })
+            .unwrap_or(Self::None)
+    }
+
+    /// Turn paths into `file://` URLs or the other way around, depending on
+    /// what the Exec key expects. Remote files are downloaded for keys that
+    /// only take local ones, and returned to be removed once opened.
+    pub fn convert(
+        self,
+        args: Vec<String>,
+        fetcher: Option<&dyn Fetch>,
+    ) -> Result<(Vec<String>, Vec<Spool>)> {
+        let kind = match self {
+            Self::None => return Ok((args, Vec::new())),
+            Self::Single(kind) | Self::Multiple(kind) => kind,
+        };
+
+        let mut downloads = Vec::new();
+        let args = args
+            .into_iter()
+            .map(|arg| {
+                Ok(match (kind, arg.parse::<UserPath>()?) {
+                    (Kind::File, UserPath::File(f)) => {
+                        f.to_string_lossy().into()
+                    }
+                    (Kind::File, UserPath::Url(url)) => {
+                        let fetcher = fetcher
+                            .ok_or_else(|| Error::NotLocal(url.to_string()))?;
+                        let spool = fetch::download(fetcher, &url)?;
+                        let path = spool.path.to_string_lossy().into();
+                        downloads.push(spool);
+                        path
+                    }
+                    (Kind::Url, path) => path.to_url()?.to_string(),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok((args, downloads))
+    }
+}
+
+/// Split the raw value of an Exec key into arguments, undoing both the
+/// escaping of string values and the quoting of arguments
+pub fn split(exec: &str) -> Result<Vec<String>> {
+    let bad_exec = |reason| Error::BadExec(exec.to_owned(), reason);
+
+    let mut args = Vec::new();
+    let mut arg = None::<String>;
+    let mut chars = unescape(exec).into_iter();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' => args.extend(arg.take()),
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '`' | '$' | '\\')) => arg.push(c),
+                            _ => {
+                                return Err(bad_exec(
+                                    "invalid escape in quotes",
+                                ))
+                            }
+                        },
+                        Some('%') => match chars.next() {
+                            Some('%') => arg.push_str("%%"),
+                            _ => return Err(bad_exec("field code in quotes")),
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err(bad_exec("unterminated quote")),
+                    }
+                }
+            }
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+
+    if args.is_empty() {
+        return Err(bad_exec("no program"));
+    }
+
+    Ok(args)
+}
+
+/// Undo the escape sequences every string value may contain
+fn unescape(value: &str) -> Vec<char> {
+    let mut unescaped = Vec::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let escaped = match (c, chars.peek()) {
+            ('\\', Some('s')) => ' ',
+            ('\\', Some('n')) => '\n',
+            ('\\', Some('t')) => '\t',
+            ('\\', Some('r')) => '\r',
+            ('\\', Some('\\')) => '\\',
+            _ => {
+                unescaped.push(c);
+                continue;
+            }
+        };
+        chars.next();
+        unescaped.push(escaped);
+    }
+
+    unescaped
+}
+
+/// Expand the field codes of an Exec key that was already split into
+/// arguments. `args` are passed wherever files or URLs are expected, or
+/// appended if the Exec key doesn't say where they go. An argument with a
+/// file or URL code embedded in it is repeated for each of `args`.
+pub fn expand(
+    exec: Vec<String>,
+    entry: &DesktopEntry,
+    args: &[String],
+) -> Vec<String> {
+    let takes_args = exec
+        .iter()
+        .any(|arg| field_codes(arg).any(|code| FILE_CODES.contains(&code)));
+
+    let mut expanded = exec
+        .into_iter()
+        .flat_map(|arg| expand_arg(arg, entry, args))
+        .collect::<Vec<_>>();
+
+    if !takes_args {
+        expanded.extend_from_slice(args);
+    }
+
+    expanded
+}
+
+/// Every field code used in an argument, without the leading `%`
+fn field_codes(arg: &str) -> impl Iterator<Item = char> + '_ {
+    let mut chars = arg.chars();
+    std::iter::from_fn(move || loop {
+        match chars.next()? {
+            '%' => return chars.next(),
+            _ => continue,
+        }
+    })
+}
+
+fn expand_arg(
+    arg: String,
+    entry: &DesktopEntry,
+    args: &[String],
+) -> Vec<String> {
+    // Codes that make up a whole argument may expand to several arguments
+    match arg.as_str() {
+        "%f" | "%F" | "%u" | "%U" => return args.to_vec(),
+        "%i" => {
+            return entry
+                .icon
+                .iter()
+                .flat_map(|icon| vec!["--icon".to_owned(), icon.clone()])
+                .collect()
+        }
+        _ => {}
+    }
+
+    if field_codes(&arg).any(|code| FILE_CODES.contains(&code)) {
+        return args
+            .iter()
+            .map(|file| expand_codes(&arg, entry, file))
+            .collect();
+    }
+
+    let expanded = expand_codes(&arg, entry, "");
+
+    // An argument made up only of codes that expanded to nothing is dropped
+    if expanded.is_empty() && !arg.is_empty() {
+        vec![]
+    } else {
+        vec![expanded]
+    }
+}
+
+/// Expand the field codes within a single argument, with `file` standing in
+/// for the file or URL codes
+fn expand_codes(arg: &str, entry: &DesktopEntry, file: &str) -> String {
+    let mut expanded = String::with_capacity(arg.len());
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('%') => expanded.push('%'),
+            Some(code) if FILE_CODES.contains(&code) => expanded.push_str(file),
+            Some('i') => expanded.push_str(entry.icon.as_deref().unwrap_or("")),
+            Some('c') => expanded.push_str(&entry.name),
+            Some('k') => expanded.push_str(&entry.path.to_string_lossy()),
+            Some(code) if DEPRECATED_CODES.contains(&code) => {}
+            // Invalid field codes are ignored
+            Some(_) | None => {}
+        }
+    }
+
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_spec_examples() -> Result<()> {
+        assert_eq!(split("fooview %F")?, ["fooview", "%F"]);
+        assert_eq!(
+            split(r#""/opt/foo bar/fooview" --title "it's \\"quoted\\"" %u"#)?,
+            ["/opt/foo bar/fooview", "--title", "it's \"quoted\"", "%u"]
+        );
+        // Reserved characters only need escaping inside quotes
+        assert_eq!(
+            split(r#"sh -c "echo \\$HOME \\`id\\` \\\\""#)?,
+            ["sh", "-c", "echo $HOME `id` \\"]
+        );
+        // String value escapes are undone before splitting
+        assert_eq!(split(r"foo\sbar  baz\tqux")?, ["foo", "bar", "baz", "qux"]);
+        assert_eq!(split(r#"foo "bar\sbaz""#)?, ["foo", "bar baz"]);
+        assert_eq!(split(r#"printf "100%%""#)?, ["printf", "100%%"]);
+        assert_eq!(split(r#"foo """#)?, ["foo", ""]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn split_rejects_malformed() {
+        for exec in &[
+            "",
+            "   ",
+            r#"foo "bar"#,
+            r#"foo "\a""#,
+            r#"foo "%f""#,
+            r#"foo "--file=%u""#,
+        ] {
+            assert!(matches!(split(exec), Err(Error::BadExec(..))), "{}", exec);
+        }
+    }
+
+    fn entry() -> DesktopEntry {
+        DesktopEntry {
+            name: "Foo Viewer".into(),
+            icon: Some("fooview".into()),
+            path: "/usr/share/applications/fooview.desktop".into(),
+            ..Default::default()
+        }
+    }
+
+    fn expand(exec: &[&str], args: &[&str]) -> Vec<String> {
+        super::expand(
+            exec.iter().map(|s| s.to_string()).collect(),
+            &entry(),
+            &args.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn files_and_urls() {
+        assert_eq!(
+            expand(&["fooview", "%F"], &["a", "b"]),
+            ["fooview", "a", "b"]
+        );
+        assert_eq!(expand(&["fooview", "%U"], &[]), ["fooview"]);
+        assert_eq!(expand(&["fooview", "%f"], &["a"]), ["fooview", "a"]);
+        assert_eq!(expand(&["fooview"], &["a"]), ["fooview", "a"]);
+    }
+
+    #[test]
+    fn icon_name_and_location() {
+        assert_eq!(
+            expand(&["fooview", "%i", "--name=%c", "%k", "%f"], &["a"]),
+            [
+                "fooview",
+                "--icon",
+                "fooview",
+                "--name=Foo Viewer",
+                "/usr/share/applications/fooview.desktop",
+                "a"
+            ]
+        );
+
+        let entry = DesktopEntry {
+            icon: None,
+            ..entry()
+        };
+        assert_eq!(
+            super::expand(vec!["fooview".into(), "%i".into()], &entry, &[]),
+            ["fooview"]
+        );
+    }
+
+    #[test]
+    fn literal_percent() {
+        assert_eq!(
+            expand(&["printf", "100%%", "%%f"], &["a"]),
+            ["printf", "100%", "%f", "a"]
+        );
+    }
+
+    #[test]
+    fn embedded_codes_are_never_merged() {
+        assert_eq!(
+            expand(&["fooview", "--file=%f"], &["a b", "c"]),
+            ["fooview", "--file=a b", "--file=c"]
+        );
+        assert_eq!(expand(&["fooview", "--file=%f"], &[]), ["fooview"]);
+    }
+
+    #[test]
+    fn arity() -> Result<()> {
+        let arity = |exec| Ok::<_, Error>(Arity::of(&split(exec)?));
+
+        assert_eq!(arity("fooview %f")?, Arity::Single(Kind::File));
+        assert_eq!(arity("fooview --url=%u")?, Arity::Single(Kind::Url));
+        assert_eq!(arity("fooview %i %F")?, Arity::Multiple(Kind::File));
+        assert_eq!(arity("fooview %U")?, Arity::Multiple(Kind::Url));
+        assert_eq!(arity("fooview %%f")?, Arity::None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn converts_between_paths_and_urls() -> Result<()> {
+        struct Stub;
+        impl Fetch for Stub {
+            fn fetch(
+                &self,
+                _: &url::Url,
+                dest: &std::path::Path,
+            ) -> Result<()> {
+                Ok(std::fs::write(dest, "")?)
+            }
+        }
+
+        let args = || {
+            vec![
+                "/tmp/a b.txt".to_owned(),
+                "file:///tmp/c%20d.txt".to_owned(),
+                "https://example.com/e.txt".to_owned(),
+            ]
+        };
+
+        let (files, downloads) =
+            Arity::Multiple(Kind::File).convert(args(), Some(&Stub))?;
+        assert_eq!(files[..2], ["/tmp/a b.txt", "/tmp/c d.txt"]);
+        assert_eq!(downloads.len(), 1);
+        assert_eq!(files[2], downloads[0].path.to_string_lossy());
+        assert!(files[2].ends_with("/e.txt"));
+
+        assert!(matches!(
+            Arity::Single(Kind::File).convert(args(), None),
+            Err(Error::NotLocal(url)) if url == "https://example.com/e.txt"
+        ));
+
+        assert_eq!(
+            Arity::Single(Kind::Url).convert(args(), None)?.0,
+            [
+                "file:///tmp/a%20b.txt",
+                "file:///tmp/c%20d.txt",
+                "https://example.com/e.txt"
+            ]
+        );
+        assert_eq!(Arity::None.convert(args(), None)?.0, args());
+
+        let (relative, _) =
+            Arity::Single(Kind::Url).convert(vec!["a.txt".into()], None)?;
+        assert!(relative[0].starts_with("file:///"));
+        assert!(relative[0].ends_with("/a.txt"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn deprecated_codes() {
+        assert_eq!(
+            expand(
+                &["fooview", "%d", "%D", "%n", "%N", "%v", "%m", "%F"],
+                &["a"]
+            ),
+            ["fooview", "a"]
+        );
+        assert_eq!(expand(&["fooview", "-x%m"], &[]), ["fooview", "-x"]);
+    }
+}
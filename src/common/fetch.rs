@@ -0,0 +1,166 @@
+use crate::{common::Spool, Error, Result};
+use std::{
+    fs::{self, File},
+    os::unix::fs::DirBuilderExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use url::Url;
+
+/// Downloads remote files for handlers that only take local ones
+pub trait Fetch {
+    fn fetch(&self, url: &Url, dest: &Path) -> Result<()>;
+}
+
+/// Runs a command with the URL as its last argument, its output being the
+/// contents of the file
+pub struct CommandFetcher(pub String);
+
+impl Fetch for CommandFetcher {
+    fn fetch(&self, url: &Url, dest: &Path) -> Result<()> {
+        let mut split = shlex::split(&self.0)
+            .filter(|split| !split.is_empty())
+            .ok_or_else(|| Error::Fetch(url.to_string(), self.0.clone()))?;
+
+        let status = Command::new(split.remove(0))
+            .args(split)
+            .arg(url.as_str())
+            .stdin(Stdio::null())
+            .stdout(File::create(dest)?)
+            .status()?;
+
+        if status.success() {
+            Ok(())
+        } else {
+            let _ = fs::remove_file(dest);
+            Err(Error::Fetch(url.to_string(), status.to_string()))
+        }
+    }
+}
+
+/// Download a URL to a new temporary dir, keeping the file name from the URL
+/// so that handlers can tell what it is
+pub fn download(fetcher: &dyn Fetch, url: &Url) -> Result<Spool> {
+    let name = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            percent_encoding::percent_decode_str(name)
+                .decode_utf8_lossy()
+                .replace('/', "_")
+        })
+        .unwrap_or_else(|| "download".into());
+
+    let spool = Spool::create(&name)?;
+    fetcher.fetch(url, &spool.path)?;
+    Ok(spool)
+}
+
+/// A new dir of handlr's own in the system's temporary dir, only accessible
+/// to the user. Fails rather than reuse a dir that already exists, as anyone
+/// could have created it.
+pub fn temp_dir() -> Result<PathBuf> {
+    let dir = std::env::temp_dir().join(format!(
+        "handlr-{}-{}",
+        std::process::id(),
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+    ));
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        cell::RefCell,
+        io::{Read, Write},
+        net::TcpListener,
+    };
+
+    /// Serve a single HTTP response on localhost
+    fn serve(status: &'static str, body: &'static str) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request);
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .unwrap();
+        });
+
+        Url::parse(&format!(
+            "http://127.0.0.1:{}/files/annual%20report.pdf",
+            port
+        ))
+        .unwrap()
+    }
+
+    fn curl() -> Option<CommandFetcher> {
+        Command::new("curl")
+            .arg("--version")
+            .stdout(Stdio::null())
+            .status()
+            .ok()?;
+        Some(CommandFetcher("curl --fail --silent --location".into()))
+    }
+
+    #[test]
+    fn command_fetcher() -> Result<()> {
+        let fetcher = match curl() {
+            Some(fetcher) => fetcher,
+            None => {
+                eprintln!("skipping command_fetcher: curl is not installed");
+                return Ok(());
+            }
+        };
+
+        let spool = download(&fetcher, &serve("200 OK", "%PDF-1.4"))?;
+        assert_eq!(spool.path.file_name().unwrap(), "annual report.pdf");
+        assert_eq!(fs::read_to_string(&spool.path)?, "%PDF-1.4");
+
+        let dir = spool.path.parent().unwrap().to_owned();
+        drop(spool);
+        assert!(!dir.exists());
+
+        assert!(matches!(
+            download(&fetcher, &serve("404 Not Found", "")),
+            Err(Error::Fetch(..))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn failed_download() {
+        /// Fails after writing part of the file, like curl does
+        struct Failing(RefCell<Option<PathBuf>>);
+        impl Fetch for Failing {
+            fn fetch(&self, url: &Url, dest: &Path) -> Result<()> {
+                fs::write(dest, "partial")?;
+                self.0.replace(Some(dest.to_owned()));
+                Err(Error::Fetch(url.to_string(), "exit status: 22".into()))
+            }
+        }
+
+        let fetcher = Failing(RefCell::new(None));
+        let url = Url::parse("https://example.com/missing.pdf").unwrap();
+        assert!(matches!(download(&fetcher, &url), Err(Error::Fetch(..))));
+
+        let dest = fetcher.0.into_inner().unwrap();
+        assert!(!dest.parent().unwrap().exists());
+    }
+}
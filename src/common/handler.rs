@@ -1,9 +1,18 @@
 use crate::{
-    common::{DesktopEntry, ExecMode},
+    common::{desktop_id, DesktopEntry, ExecMode, Spool, Unavailable},
     Error, Result,
 };
-use std::{fmt::Display, convert::TryFrom, ffi::OsString, path::PathBuf, str::FromStr};
+use std::{
+    ffi::{OsStr, OsString},
+    fmt::Display,
+    os::unix::ffi::OsStrExt,
+    path::PathBuf,
+    process::Child,
+    str::FromStr,
+};
 
+/// A desktop file ID, optionally followed by one of its actions as in
+/// `okular.desktop:presentation`
 #[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Handler(OsString);
 
@@ -25,23 +34,67 @@ impl Handler {
         Self(name)
     }
     pub fn get_path(name: &std::ffi::OsStr) -> Option<PathBuf> {
-        let mut path = PathBuf::from("applications");
-        path.push(name);
-        xdg::BaseDirectories::new().ok()?.find_data_file(path)
+        desktop_id::find(name).ok()?
     }
     pub fn resolve(name: OsString) -> Result<Self> {
-        let path = Self::get_path(&name)
-            .ok_or(Error::NotFound(name.to_string_lossy().into()))?;
-        DesktopEntry::try_from(path)?;
-        Ok(Self(name))
+        let handler = Self(name);
+        // Hidden entries count as deleted
+        if handler.get_entry()?.hidden {
+            return Err(Error::NotFound(handler.to_string()));
+        }
+        Ok(handler)
+    }
+    /// The same handler, running the given action instead
+    pub fn with_action(&self, action: &str) -> Result<Self> {
+        let mut name = self.split().0.to_owned();
+        name.push(":");
+        name.push(action);
+        Self::resolve(name)
+    }
+    /// The desktop file ID and the action, if any
+    fn split(&self) -> (&OsStr, Option<&str>) {
+        let bytes = self.0.as_bytes();
+        let split = bytes
+            .iter()
+            .rposition(|&b| b == b':')
+            .filter(|&i| bytes[..i].ends_with(b".desktop"))
+            .and_then(|i| {
+                let action = std::str::from_utf8(&bytes[i + 1..]).ok()?;
+                Some((OsStr::from_bytes(&bytes[..i]), action))
+            });
+
+        match split {
+            Some((id, action)) => (id, Some(action)),
+            None => (&self.0, None),
+        }
+    }
+    /// Whether the handler's desktop file can currently be found
+    pub fn exists(&self) -> bool {
+        Self::resolve(self.0.clone()).is_ok()
+    }
+    /// Why the handler can't be used on the current desktop, if it can't
+    pub fn unavailable(&self) -> Option<Unavailable> {
+        self.get_entry()
+            .ok()?
+            .unavailable(&crate::utils::current_desktops())
     }
     pub fn get_entry(&self) -> Result<DesktopEntry> {
-        DesktopEntry::try_from(Self::get_path(&self.0).unwrap())
+        let (id, action) = self.split();
+        let path = Self::get_path(id)
+            .ok_or_else(|| Error::NotFound(self.to_string()))?;
+        let entry = DesktopEntry::read(id.to_owned(), path)?;
+        match action {
+            Some(action) => entry.with_action(action),
+            None => Ok(entry),
+        }
     }
     pub fn launch(&self, args: Vec<String>) -> Result<()> {
-        self.get_entry()?.exec(ExecMode::Launch, args)
+        self.get_entry()?.exec(ExecMode::Launch, args)?;
+        Ok(())
     }
-    pub fn open(&self, args: Vec<String>) -> Result<()> {
+    /// The processes left running in the background, and the files
+    /// downloaded for them
+    pub fn open(&self, args: Vec<String>) -> Result<(Vec<Child>, Vec<Spool>)> {
         self.get_entry()?.exec(ExecMode::Open, args)
     }
 }
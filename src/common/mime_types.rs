@@ -1,24 +1,44 @@
-use crate::{Error, Result};
+use crate::{config::Detect, Error, Result};
+use itertools::Itertools;
 use mime::Mime;
-use std::{convert::TryFrom, path::Path, str::FromStr};
+use once_cell::sync::Lazy;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 use url::Url;
 
+pub static MIME_DB: Lazy<MimeDb> =
+    Lazy::new(|| MimeDb::load().unwrap_or_default());
+
+/// Enough of a file's contents for any magic rule to match
+const SNIFF_LEN: u64 = 64 * 1024;
+
 // A mime derived from a path or URL
 #[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct MimeType(pub Mime);
 
 impl MimeType {
     fn from_ext(ext: &str) -> Result<Mime> {
-        match &*xdg_mime::SharedMimeInfo::new()
-            .get_mime_types_from_file_name(ext)
-        {
-            [m] if m == &mime::APPLICATION_OCTET_STREAM => {
-                Err(Error::Ambiguous(ext.into()))
-            }
-            [guess, ..] => Ok(guess.clone()),
-            [] => unreachable!(),
+        match &*MIME_DB.glob(ext) {
+            [] => Err(Error::Ambiguous(ext.into())),
+            [mime] => Ok(mime.clone()),
+            mimes => Err(Error::AmbiguousExtension(
+                ext.into(),
+                mimes.iter().join(", "),
+            )),
         }
     }
+
+    pub fn from_path(path: &Path, detect: Detect) -> Result<Self> {
+        let mime = mime_to_option(Detection::of_path(path, detect).mime)
+            .ok_or_else(|| Error::Ambiguous(path.to_owned()))?;
+
+        Ok(Self(mime))
+    }
 }
 
 impl From<&Url> for MimeType {
@@ -31,16 +51,135 @@ impl From<&Url> for MimeType {
     }
 }
 
-impl TryFrom<&Path> for MimeType {
-    type Error = Error;
-    fn try_from(path: &Path) -> Result<Self> {
+/// The mime of a path or URL along with what it was detected from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Detection {
+    pub mime: Mime,
+    /// Mimes whose globs match the file name best
+    pub globs: Vec<Mime>,
+    /// The mime sniffed from the contents, with the priority of the magic
+    /// rule that matched. 80 and up is a certain match.
+    pub magic: Option<(Mime, u32)>,
+    pub uncertain: bool,
+}
+
+impl Detection {
+    /// Only reads the file if the strategy calls for its contents, or the
+    /// file name alone is ambiguous
+    pub fn of_path(path: &Path, detect: Detect) -> Self {
+        let name = path.file_name().and_then(|name| name.to_str());
+        let globs = name.map(|name| MIME_DB.glob(name)).unwrap_or_default();
+        let metadata = std::fs::metadata(path).ok();
+
+        if metadata.as_ref().is_some_and(|m| m.is_dir()) {
+            return Self {
+                mime: "inode/directory".parse().unwrap(),
+                globs,
+                magic: None,
+                uncertain: false,
+            };
+        }
+
+        if detect == Detect::Extension
+            || (detect == Detect::Combined && globs.len() == 1)
+        {
+            return Self {
+                mime: globs
+                    .first()
+                    .cloned()
+                    .unwrap_or(mime::APPLICATION_OCTET_STREAM),
+                uncertain: globs.len() != 1,
+                globs,
+                magic: None,
+            };
+        }
+
         let db = xdg_mime::SharedMimeInfo::new();
-        let guess = db.guess_mime_type().path(&path).guess();
 
-        let mime = mime_to_option(guess.mime_type().clone())
-            .ok_or_else(|| Error::Ambiguous(path.to_owned()))?;
+        let mut data = Vec::new();
+        let _ = File::open(path)
+            .and_then(|file| file.take(SNIFF_LEN).read_to_end(&mut data));
 
-        Ok(Self(mime))
+        let magic = match data.is_empty() {
+            true => None,
+            false => db.get_mime_type_for_data(&data),
+        };
+
+        let mut guess = db.guess_mime_type();
+        guess.data(&data);
+        if let Some(metadata) = metadata {
+            guess.metadata(metadata);
+        }
+        let guess = guess.guess();
+        let mut sniffed = guess.mime_type().clone();
+        let mut uncertain = guess.uncertain();
+        if sniffed == mime::APPLICATION_OCTET_STREAM && looks_like_text(&data) {
+            sniffed = mime::TEXT_PLAIN;
+            uncertain = false;
+        }
+        // Like GIO, never let the contents alone make a file launchable
+        if sniffed == "application/x-desktop"
+            && name.is_none_or(|name| !name.ends_with(".desktop"))
+        {
+            sniffed = mime::TEXT_PLAIN;
+        }
+
+        let (mime, uncertain) = match globs.first() {
+            Some(first) if detect == Detect::Combined => {
+                // The contents break the tie if they are one of the
+                // candidates, or more specific than one
+                match MIME_DB
+                    .ancestors(&sniffed)
+                    .iter()
+                    .any(|mime| globs.contains(mime))
+                {
+                    true => (sniffed, false),
+                    false => (first.clone(), true),
+                }
+            }
+            _ => (sniffed, uncertain),
+        };
+
+        Self {
+            mime,
+            globs,
+            magic,
+            uncertain,
+        }
+    }
+
+    pub fn of_url(url: &Url) -> Self {
+        Self {
+            mime: MimeType::from(url).0,
+            globs: Vec::new(),
+            magic: None,
+            uncertain: false,
+        }
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        let essences = |mimes: &[Mime]| {
+            mimes
+                .iter()
+                .map(|m| m.essence_str().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        json::object! {
+            mime: self.mime.essence_str(),
+            uncertain: self.uncertain,
+            glob: json::object! {
+                matched: self.globs.contains(&self.mime),
+                mimes: essences(&self.globs),
+            },
+            magic: self.magic.as_ref().map(|(mime, priority)| json::object! {
+                matched: mime == &self.mime,
+                mime: mime.essence_str(),
+                priority: *priority,
+            }),
+            aliases: essences(&MIME_DB.aliases_of(&self.mime)),
+            parents: essences(&MIME_DB.ancestors(&self.mime)[1..]),
+        }
     }
 }
 
@@ -52,6 +191,249 @@ fn mime_to_option(mime: Mime) -> Option<Mime> {
     }
 }
 
+/// Whether contents no magic rule matched are text rather than binary
+fn looks_like_text(data: &[u8]) -> bool {
+    !data.is_empty()
+        && !data.contains(&0)
+        && std::str::from_utf8(data).map_or_else(
+            // Cut off in the middle of a character
+            |e| e.error_len().is_none(),
+            |_| true,
+        )
+}
+
+/// Aliases, subclasses and globs from the shared MIME database
+#[derive(Debug, Default)]
+pub struct MimeDb {
+    aliases: HashMap<Mime, Mime>,
+    parents: HashMap<Mime, Vec<Mime>>,
+    globs: Vec<Glob>,
+}
+
+/// A line of `globs2`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Glob {
+    weight: u32,
+    mime: Mime,
+    pattern: String,
+    case_sensitive: bool,
+}
+
+impl MimeDb {
+    fn load() -> Result<Self> {
+        let xdg = xdg::BaseDirectories::new()?;
+        Ok(Self::read(
+            std::iter::once(xdg.get_data_home())
+                .chain(xdg.get_data_dirs())
+                .map(|dir| dir.join("mime")),
+        ))
+    }
+
+    /// Read the `aliases`, `subclasses` and `globs2` files of mime dirs,
+    /// highest precedence first
+    fn read(dirs: impl IntoIterator<Item = PathBuf>) -> Self {
+        let mut db = Self::default();
+        // Mimes whose globs from lower precedence dirs are dropped
+        let mut no_globs = HashSet::new();
+
+        for dir in dirs {
+            let (cleared, globs): (Vec<_>, Vec<_>) =
+                read_globs(&dir.join("globs2"))
+                    .into_iter()
+                    .partition(|glob| glob.pattern == "__NOGLOBS__");
+            for glob in globs {
+                if !no_globs.contains(&glob.mime) && !db.globs.contains(&glob) {
+                    db.globs.push(glob);
+                }
+            }
+            no_globs.extend(cleared.into_iter().map(|glob| glob.mime));
+
+            for (alias, mime) in read_pairs(&dir.join("aliases")) {
+                db.aliases.entry(alias).or_insert(mime);
+            }
+            for (mime, parent) in read_pairs(&dir.join("subclasses")) {
+                let parents = db.parents.entry(mime).or_default();
+                if !parents.contains(&parent) {
+                    parents.push(parent);
+                }
+            }
+        }
+
+        db
+    }
+
+    /// What a mime is an alias of
+    pub fn unalias(&self, mime: &Mime) -> Option<&Mime> {
+        self.aliases.get(mime)
+    }
+
+    /// The mimes whose globs match a file name best: those of the highest
+    /// weight, then the longest pattern, case-sensitive patterns first
+    pub fn glob(&self, file_name: &str) -> Vec<Mime> {
+        let lowercase = file_name.to_lowercase();
+        let matches = self
+            .globs
+            .iter()
+            .filter(|glob| match glob.case_sensitive {
+                true => glob_match(&glob.pattern, file_name),
+                false => glob_match(&glob.pattern.to_lowercase(), &lowercase),
+            })
+            .collect::<Vec<_>>();
+
+        let rank = |glob: &Glob| {
+            (glob.weight, glob.pattern.len(), glob.case_sensitive)
+        };
+        let best = matches.iter().map(|glob| rank(glob)).max();
+
+        matches
+            .into_iter()
+            .filter(|glob| Some(rank(glob)) == best)
+            .map(|glob| glob.mime.clone())
+            .unique()
+            .collect()
+    }
+
+    /// The extension a file of this mime is best given, from its simplest
+    /// glob of the highest weight
+    pub fn extension(&self, mime: &Mime) -> Option<&str> {
+        self.globs
+            .iter()
+            .filter(|glob| &glob.mime == mime)
+            .filter_map(|glob| {
+                let ext = glob.pattern.strip_prefix("*.")?;
+                match ext.contains(&['*', '?', '['][..]) {
+                    true => None,
+                    false => Some((glob.weight, ext)),
+                }
+            })
+            // The first of those with the highest weight
+            .rev()
+            .max_by_key(|(weight, _)| *weight)
+            .map(|(_, ext)| ext)
+    }
+
+    /// Other names of a mime
+    pub fn aliases_of(&self, mime: &Mime) -> Vec<Mime> {
+        let mut aliases = self
+            .aliases
+            .iter()
+            .filter(|(_, canonical)| *canonical == mime)
+            .map(|(alias, _)| alias.clone())
+            .collect::<Vec<_>>();
+        aliases.sort();
+        aliases
+    }
+
+    /// The mime itself, then what it is an alias of and its ancestors,
+    /// closest first. Text is implicitly a subclass of `text/plain`.
+    pub fn ancestors(&self, mime: &Mime) -> Vec<Mime> {
+        let mut chain = vec![mime.clone()];
+
+        let mut i = 0;
+        while let Some(current) = chain.get(i).cloned() {
+            let next = self
+                .aliases
+                .get(&current)
+                .into_iter()
+                .chain(self.parents.get(&current).into_iter().flatten());
+            for mime in next {
+                if !chain.contains(mime) {
+                    chain.push(mime.clone());
+                }
+            }
+            i += 1;
+        }
+
+        if mime.type_() == mime::TEXT && !chain.contains(&mime::TEXT_PLAIN) {
+            chain.push(mime::TEXT_PLAIN);
+        }
+
+        chain
+    }
+}
+
+/// Lines of `weight:mime:pattern[:flags]`
+fn read_globs(path: &Path) -> Vec<Glob> {
+    std::fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            Some(Glob {
+                weight: fields.next()?.parse().ok()?,
+                mime: fields.next()?.parse().ok()?,
+                pattern: fields.next()?.to_owned(),
+                case_sensitive: fields
+                    .next()
+                    .is_some_and(|flags| flags.split(',').any(|f| f == "cs")),
+            })
+        })
+        .collect()
+}
+
+/// Whether a file name matches a shell glob of `*`, `?` and `[...]`
+fn glob_match(pattern: &str, name: &str) -> bool {
+    fn matches(pattern: &[char], name: &[char]) -> bool {
+        match pattern {
+            [] => name.is_empty(),
+            ['*', rest @ ..] => {
+                (0..=name.len()).any(|i| matches(rest, &name[i..]))
+            }
+            ['?', rest @ ..] => !name.is_empty() && matches(rest, &name[1..]),
+            ['[', class @ ..] => {
+                let end = match class.iter().skip(1).position(|&c| c == ']') {
+                    Some(end) => end + 1,
+                    None => return literal(pattern, name),
+                };
+                let (negated, set) = match class[0] {
+                    '!' | '^' => (true, &class[1..end]),
+                    _ => (false, &class[..end]),
+                };
+                let c = match name.first() {
+                    Some(&c) => c,
+                    None => return false,
+                };
+                let mut found = false;
+                let mut i = 0;
+                while i < set.len() {
+                    if i + 2 < set.len() && set[i + 1] == '-' {
+                        found |= (set[i]..=set[i + 2]).contains(&c);
+                        i += 3;
+                    } else {
+                        found |= set[i] == c;
+                        i += 1;
+                    }
+                }
+                found != negated && matches(&class[end + 1..], &name[1..])
+            }
+            _ => literal(pattern, name),
+        }
+    }
+    fn literal(pattern: &[char], name: &[char]) -> bool {
+        name.first() == pattern.first()
+            && !name.is_empty()
+            && matches(&pattern[1..], &name[1..])
+    }
+
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    matches(&pattern, &name)
+}
+
+/// Lines of two mimes separated by a space, as in `aliases` and `subclasses`
+fn read_pairs(path: &Path) -> Vec<(Mime, Mime)> {
+    std::fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let (a, b) = line.split_once(' ')?;
+            Some((a.trim().parse().ok()?, b.trim().parse().ok()?))
+        })
+        .collect()
+}
+
 // Mime derived from user input: extension(.pdf) or type like image/jpg
 #[derive(Debug)]
 pub struct MimeOrExtension(pub Mime);
@@ -93,31 +475,180 @@ mod tests {
     #[test]
     fn from_path() -> Result<()> {
         assert_eq!(
-            MimeType::try_from(Path::new("."))?.0.essence_str(),
+            MimeType::from_path(Path::new("."), Detect::Combined)?
+                .0
+                .essence_str(),
             "inode/directory"
         );
         assert_eq!(
-            MimeType::try_from(Path::new("./tests/rust.vim"))?.0,
+            MimeType::from_path(
+                Path::new("./tests/rust.vim"),
+                Detect::Combined
+            )?
+            .0,
             "text/plain"
         );
         assert_eq!(
-            MimeType::try_from(Path::new("./tests/cat"))?.0,
+            MimeType::from_path(Path::new("./tests/cat"), Detect::Combined)?.0,
             "application/x-shellscript"
         );
         assert_eq!(
-            MimeType::try_from(Path::new("./tests/SettingsWidgetFdoSecrets.ui"))?.0,
+            MimeType::from_path(
+                Path::new("./tests/SettingsWidgetFdoSecrets.ui"),
+                Detect::Combined
+            )?
+            .0,
             "application/x-designer"
         );
 
         Ok(())
     }
 
+    #[test]
+    fn ancestors() {
+        let db = MimeDb::read(vec![PathBuf::from("tests/mime")]);
+        let ancestors = |mime: &str| {
+            db.ancestors(&mime.parse().unwrap())
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            ancestors("text/x-c++src"),
+            ["text/x-c++src", "text/x-csrc", "text/plain"]
+        );
+        assert_eq!(
+            ancestors("application/x-shellscript"),
+            [
+                "application/x-shellscript",
+                "application/x-executable",
+                "text/plain"
+            ]
+        );
+        assert_eq!(
+            ancestors("application/x-pdf"),
+            ["application/x-pdf", "application/pdf"]
+        );
+        // Implicitly plain text
+        assert_eq!(
+            ancestors("text/x-unknown"),
+            ["text/x-unknown", "text/plain"]
+        );
+        assert_eq!(ancestors("text/plain"), ["text/plain"]);
+        assert_eq!(ancestors("image/png"), ["image/png"]);
+        assert_eq!(
+            db.aliases_of(&mime::APPLICATION_PDF),
+            ["application/x-pdf".parse::<Mime>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn detection() {
+        // The name says nothing, the contents do
+        let script =
+            Detection::of_path(Path::new("./tests/cat"), Detect::Combined);
+        assert_eq!(script.mime, "application/x-shellscript");
+        assert!(script.globs.is_empty());
+        assert_eq!(
+            script.magic.as_ref().map(|(mime, _)| mime.essence_str()),
+            Some("application/x-shellscript")
+        );
+        assert_eq!(script.to_json()["magic"]["matched"], true);
+        assert_eq!(script.to_json()["glob"]["matched"], false);
+
+        let page =
+            Detection::of_path(Path::new("./tests/p.html"), Detect::Combined);
+        assert_eq!(page.mime, "text/html");
+        assert_eq!(page.globs, [mime::TEXT_HTML]);
+
+        let url = Url::parse("https://example.com/a.pdf").unwrap();
+        assert_eq!(Detection::of_url(&url).mime, "x-scheme-handler/https");
+    }
+
+    #[test]
+    fn strategies() {
+        let detect = |path: &str, detect: Detect| {
+            Detection::of_path(Path::new(path), detect).mime.to_string()
+        };
+
+        let misnamed = "./tests/script.png";
+        assert_eq!(detect(misnamed, Detect::Extension), "image/png");
+        assert_eq!(detect(misnamed, Detect::Combined), "image/png");
+        assert_eq!(
+            detect(misnamed, Detect::Content),
+            "application/x-shellscript"
+        );
+
+        // Names that several mimes claim equally are settled by the contents
+        let ambiguous = Detection::of_path(
+            Path::new("./tests/strings.ts"),
+            Detect::Combined,
+        );
+        assert_eq!(ambiguous.globs.len(), 2);
+        assert_eq!(ambiguous.mime, "text/vnd.trolltech.linguist");
+        assert!(!ambiguous.uncertain);
+        let guessed = Detection::of_path(
+            Path::new("./tests/strings.ts"),
+            Detect::Extension,
+        );
+        assert!(guessed.uncertain);
+        assert_eq!(guessed.magic, None);
+
+        assert_eq!(detect("./tests", Detect::Extension), "inode/directory");
+        assert_eq!(
+            detect("./tests/nonexistent.pdf", Detect::Combined),
+            "application/pdf"
+        );
+    }
+
+    #[test]
+    fn globs() {
+        let db = MimeDb::read(vec![PathBuf::from("tests/mime")]);
+        let glob = |name: &str| {
+            db.glob(name)
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        // Highest weight wins
+        assert_eq!(glob("index.html"), ["text/html"]);
+        assert_eq!(glob("INDEX.HTML"), ["text/html"]);
+        assert_eq!(glob("libfoo.so.1"), ["application/x-sharedlib"]);
+        // Then the longest pattern
+        assert_eq!(glob("CMakeLists.txt"), ["text/x-cmake"]);
+        assert_eq!(glob("notes.txt"), ["text/plain"]);
+        // Then case-sensitive ones
+        assert_eq!(glob("main.C"), ["text/x-c++src"]);
+        assert_eq!(glob("main.c"), ["text/x-csrc"]);
+        assert_eq!(glob("Makefile"), ["text/x-makefile"]);
+        assert_eq!(glob("ls.1"), ["application/x-troff-man"]);
+        assert_eq!(glob("001.vdr"), ["video/mpeg"]);
+        assert_eq!(glob("a01.vdr"), Vec::<String>::new());
+        assert_eq!(glob("notes.txt~"), ["application/x-trash"]);
+        assert_eq!(glob("a.ts"), ["video/mp2t", "text/vnd.trolltech.linguist"]);
+        assert_eq!(glob("README"), Vec::<String>::new());
+
+        let extension = |mime: &str| db.extension(&mime.parse().unwrap());
+        assert_eq!(extension("text/html"), Some("html"));
+        assert_eq!(extension("text/x-c++src"), Some("C"));
+        assert_eq!(extension("text/x-makefile"), None);
+        assert_eq!(extension("application/x-sharedlib"), None);
+    }
+
     #[test]
     fn from_ext() -> Result<()> {
         assert_eq!(".mp3".parse::<MimeOrExtension>()?.0, "audio/mpeg");
         assert_eq!("audio/mpeg".parse::<MimeOrExtension>()?.0, "audio/mpeg");
         ".".parse::<MimeOrExtension>().unwrap_err();
         "audio/".parse::<MimeOrExtension>().unwrap_err();
+        // Weights settle which of several globs applies
+        assert_eq!(".html".parse::<MimeOrExtension>()?.0, "text/html");
+        assert!(matches!(
+            ".ts".parse::<MimeOrExtension>(),
+            Err(Error::AmbiguousExtension(..))
+        ));
 
         Ok(())
     }
@@ -1,11 +1,17 @@
 mod db;
 mod desktop_entry;
+pub mod desktop_id;
+mod exec;
+mod fetch;
 mod handler;
 mod mime_types;
 mod path;
+mod spool;
 
 pub use self::db::autocomplete as db_autocomplete;
-pub use desktop_entry::{DesktopEntry, Mode as ExecMode};
+pub use desktop_entry::{Action, DesktopEntry, Mode as ExecMode, Unavailable};
+pub use fetch::{CommandFetcher, Fetch};
 pub use handler::Handler;
-pub use mime_types::{MimeOrExtension, MimeType};
+pub use mime_types::{Detection, MimeOrExtension, MimeType, MIME_DB};
 pub use path::UserPath;
+pub use spool::Spool;
@@ -1,10 +1,14 @@
 use url::Url;
 
-use crate::{common::MimeType, Error, Result};
+use crate::{
+    common::{desktop_id, DesktopEntry, Detection, MimeType},
+    config::Detect,
+    Error, Result,
+};
 use std::{
     convert::TryFrom,
     fmt::{Display, Formatter},
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
 };
 
@@ -14,10 +18,55 @@ pub enum UserPath {
 }
 
 impl UserPath {
-    pub fn get_mime(&self) -> Result<MimeType> {
+    pub fn get_mime(&self, detect: Detect) -> Result<MimeType> {
         match self {
             Self::Url(url) => Ok(url.into()),
-            Self::File(f) => MimeType::try_from(f.as_path()),
+            Self::File(f) => MimeType::from_path(f, detect),
+        }
+    }
+
+    /// The mime and how it was detected
+    pub fn detect(&self, detect: Detect) -> Detection {
+        match self {
+            Self::Url(url) => Detection::of_url(url),
+            Self::File(f) => Detection::of_path(f, detect),
+        }
+    }
+
+    /// The entry of a local .desktop file or Windows .url shortcut, which is
+    /// opened by launching it or following its link rather than as a text
+    /// file. Only trusted files are launched, others are opened as text.
+    pub fn desktop_entry(&self) -> Option<DesktopEntry> {
+        match self {
+            Self::File(f) if f.extension() == Some("desktop".as_ref()) => {
+                let entry = DesktopEntry::try_from(f.clone())
+                    .ok()
+                    .filter(|entry| !entry.hidden)?;
+                let trusted = entry.url.is_some()
+                    || desktop_id::applications_dirs()
+                        .is_ok_and(|dirs| is_trusted(f, &dirs));
+                trusted.then_some(entry)
+            }
+            Self::File(f) if f.extension() == Some("url".as_ref()) => {
+                DesktopEntry::read_shortcut(f.clone()).ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// As a URL, local paths becoming absolute `file://` URLs
+    pub fn to_url(&self) -> Result<Url> {
+        match self {
+            Self::Url(url) => Ok(url.clone()),
+            Self::File(f) => {
+                let path = if f.is_relative() {
+                    std::env::current_dir()?.join(f)
+                } else {
+                    f.clone()
+                };
+                Url::from_file_path(&path)
+                    .map_err(|_| Error::BadPath(path.to_string_lossy().into()))
+            }
         }
     }
 }
@@ -25,7 +74,7 @@ impl UserPath {
 impl FromStr for UserPath {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let normalized = match url::Url::parse(&s) {
+        let normalized = match url::Url::parse(s) {
             Ok(url) if url.scheme() == "file" => {
                 let path = url
                     .to_file_path()
@@ -44,8 +93,63 @@ impl FromStr for UserPath {
 impl Display for UserPath {
     fn fmt(&self, fmt: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
         match self {
-            Self::File(f) => fmt.write_str(&f.to_string_lossy().to_string()),
-            Self::Url(u) => fmt.write_str(&u.to_string()),
+            Self::File(f) => fmt.write_str(&f.to_string_lossy()),
+            Self::Url(u) => fmt.write_str(u.as_str()),
         }
     }
 }
+
+/// Whether a .desktop file may be launched, by the rule GIO and file managers
+/// follow: the user made it executable and owns it, or it is installed in an
+/// applications dir
+fn is_trusted(path: &Path, applications_dirs: &[PathBuf]) -> bool {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let own_executable = path.metadata().is_ok_and(|m| {
+        m.is_file()
+            && m.uid() == unsafe { libc::getuid() }
+            && m.permissions().mode() & 0o100 != 0
+    });
+
+    own_executable
+        || path.canonicalize().is_ok_and(|path| {
+            applications_dirs.iter().any(|dir| {
+                dir.canonicalize().is_ok_and(|dir| path.starts_with(dir))
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    #[test]
+    fn trusted_desktop_files() -> Result<()> {
+        let dir = TempDir::new("trust");
+        let applications = dir.join("applications");
+        fs::create_dir_all(&applications)?;
+
+        let downloaded = dir.join("cmus.desktop");
+        fs::copy("tests/cmus.desktop", &downloaded)?;
+        fs::set_permissions(&downloaded, fs::Permissions::from_mode(0o644))?;
+        let installed = applications.join("cmus.desktop");
+        fs::copy(&downloaded, &installed)?;
+        let link = dir.join("link.desktop");
+        fs::copy("tests/link.desktop", &link)?;
+        fs::set_permissions(&link, fs::Permissions::from_mode(0o644))?;
+
+        // Not launched, so it is opened as text like any other file
+        let path = UserPath::File(downloaded.clone());
+        assert!(path.desktop_entry().is_none());
+        assert!(is_trusted(&installed, &[applications]));
+        // Following a link runs nothing
+        assert!(UserPath::File(link).desktop_entry().is_some());
+
+        fs::set_permissions(&downloaded, fs::Permissions::from_mode(0o755))?;
+        assert!(path.desktop_entry().is_some());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{
+    common::{fetch, Detection, MIME_DB},
+    config::Detect,
+    Result,
+};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Read},
+    os::unix::fs::OpenOptionsExt,
+    path::PathBuf,
+    process::Child,
+    time::{Duration, Instant},
+};
+
+/// A temporary file for handlers to open, of data piped to handlr or
+/// downloaded for them. The file is removed when this is dropped.
+pub struct Spool {
+    pub path: PathBuf,
+    created: Instant,
+}
+
+impl Spool {
+    /// Save the input, naming the file with the extension of the mime its
+    /// contents are sniffed as
+    pub fn new(mut input: impl Read) -> Result<Self> {
+        let mut spool = Self::create("stdin")?;
+        io::copy(
+            &mut input,
+            &mut OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&spool.path)?,
+        )?;
+
+        let mime = Detection::of_path(&spool.path, Detect::Content).mime;
+        if let Some(ext) = MIME_DB.extension(&mime) {
+            let path = spool.path.with_extension(ext);
+            fs::rename(&spool.path, &path)?;
+            spool.path = path;
+        }
+
+        Ok(spool)
+    }
+
+    /// A file of the given name in a new temporary dir, which is left to
+    /// the caller to write
+    pub(super) fn create(name: &str) -> Result<Self> {
+        Ok(Self {
+            path: fetch::temp_dir()?.join(name),
+            created: Instant::now(),
+        })
+    }
+
+    /// Wait for the handlers they were given to to exit, and for at least
+    /// `delay` after each was created for those that hand them off to
+    /// another process and exit right away
+    pub fn keep_until(
+        spools: Vec<Self>,
+        handlers: Vec<Child>,
+        delay: Duration,
+    ) -> Result<()> {
+        for mut handler in handlers {
+            handler.wait()?;
+        }
+        for spool in spools {
+            if let Some(rest) = delay.checked_sub(spool.created.elapsed()) {
+                std::thread::sleep(rest);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        if let Some(dir) = self.path.parent() {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn spool() -> Result<()> {
+        let pdf = Spool::new(&b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n"[..])?;
+        assert_eq!(pdf.path.file_name().unwrap(), "stdin.pdf");
+        assert_eq!(fs::read(&pdf.path)?, b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n");
+
+        let dir = pdf.path.parent().unwrap().to_owned();
+        assert_eq!(fs::metadata(&dir)?.permissions().mode() & 0o777, 0o700);
+        assert_eq!(
+            fs::metadata(&pdf.path)?.permissions().mode() & 0o777,
+            0o600
+        );
+        Spool::keep_until(vec![pdf], Vec::new(), Duration::ZERO)?;
+        assert!(!dir.exists());
+
+        let text = Spool::new(&b"just some notes\n"[..])?;
+        assert_eq!(text.path.file_name().unwrap(), "stdin.txt");
+
+        // Nothing to go by
+        let binary = Spool::new(&[0xde, 0xad, 0x00, 0xef][..])?;
+        assert_eq!(binary.path.file_name().unwrap(), "stdin");
+
+        Ok(())
+    }
+}
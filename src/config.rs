@@ -1,4 +1,8 @@
-use crate::{apps::SystemApps, common::Handler, Error, Result};
+use crate::{
+    apps::SystemApps,
+    common::{CommandFetcher, DesktopEntry, Handler},
+    Error, Result,
+};
 use mime::Mime;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
@@ -11,6 +15,59 @@ pub static CONFIG: Lazy<Config> = Lazy::new(Config::load);
 pub struct Config {
     pub enable_selector: bool,
     pub selector: String,
+    /// How many old versions of mimeapps.list to keep around
+    pub max_backups: usize,
+    /// Command that prints the contents of the URL given as its last
+    /// argument, e.g. `curl --fail --silent --location`. Used to download
+    /// remote files for handlers that only open local ones.
+    pub fetcher: Option<String>,
+    /// Where to run handlers whose .desktop file doesn't set `Path`
+    pub working_dir: WorkingDir,
+    /// What the mime of a file is detected from
+    pub detection: Detect,
+    /// Seconds to keep data piped to `open -` and downloaded files around at
+    /// the least, for handlers that pass them on to another process and exit
+    /// right away
+    pub stdin_cleanup_delay: u64,
+}
+
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkingDir {
+    /// Wherever handlr was run from
+    #[default]
+    Inherit,
+    /// The dir of the first file being opened
+    Parent,
+    Home,
+}
+
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Detect {
+    /// Only the file name, never reading the file
+    Extension,
+    /// Only the contents, for misnamed files
+    Content,
+    /// The file name, with the contents breaking ties
+    #[default]
+    Combined,
+}
+
+impl FromStr for Detect {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "extension" => Ok(Self::Extension),
+            "content" => Ok(Self::Content),
+            "combined" => Ok(Self::Combined),
+            _ => Err(Error::BadDetect(s.to_owned())),
+        }
+    }
 }
 
 impl Default for Config {
@@ -18,17 +75,21 @@ impl Default for Config {
         Config {
             enable_selector: false,
             selector: "rofi -dmenu -i -p 'Open With: '".into(),
+            max_backups: 10,
+            fetcher: None,
+            working_dir: WorkingDir::Inherit,
+            detection: Detect::Combined,
+            stdin_cleanup_delay: 30,
         }
     }
 }
 
 impl Config {
-    pub fn terminal() -> Result<String> {
+    pub fn terminal() -> Result<DesktopEntry> {
         let terminal_entry = crate::apps::APPS
             .get_handler(&Mime::from_str("x-scheme-handler/terminal").unwrap())
             .ok()
-            .map(|h| h.get_entry().ok())
-            .flatten();
+            .and_then(|h| h.get_entry().ok());
 
         terminal_entry
             .or_else(|| {
@@ -36,8 +97,7 @@ impl Config {
                     .ok()?
                     .find(|(_handler, entry)| {
                         entry.categories.contains_key("TerminalEmulator")
-                    })
-                    .map(|e| e.clone())?;
+                    })?;
 
                 crate::utils::notify(
                     "handlr",
@@ -48,17 +108,22 @@ impl Config {
                 ).ok()?;
 
                 let mut apps = (*crate::apps::APPS).clone();
-                apps.set_handler(
-                    Mime::from_str("x-scheme-handler/terminal").unwrap(),
-                    Handler::assume_valid(entry.0),
-                );
-                apps.save().ok()?;
+                let handler = Handler::assume_valid(entry.0);
+                apps.update(|apps| {
+                    apps.set_handler(
+                        Mime::from_str("x-scheme-handler/terminal").unwrap(),
+                        handler,
+                    )
+                })
+                .ok()?;
 
                 Some(entry.1)
             })
-            .map(|e| e.exec)
             .ok_or(Error::NoTerminal)
     }
+    pub fn fetcher(&self) -> Option<CommandFetcher> {
+        self.fetcher.clone().map(CommandFetcher)
+    }
     pub fn load() -> Self {
         confy::load("handlr").unwrap()
     }
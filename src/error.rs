@@ -1,7 +1,7 @@
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error(transparent)]
-    ParseApps(#[from] pest::error::Error<crate::apps::MimeappsRule>),
+    ParseApps(#[from] Box<pest::error::Error<crate::apps::MimeappsRule>>),
     #[error(transparent)]
     Io(#[from] std::io::Error),
     #[error(transparent)]
@@ -12,12 +12,18 @@ pub enum Error {
     NotFound(String),
     #[error("could not figure out the mime type of '{0}'")]
     Ambiguous(std::path::PathBuf),
+    #[error("'{0}' could be any of {1}, give the mime instead")]
+    AmbiguousExtension(String, String),
     #[error(transparent)]
     BadMimeType(#[from] mime::FromStrError),
     #[error("bad mime: {0}")]
     InvalidMime(mime::Mime),
     #[error("malformed desktop entry at {0}")]
     BadEntry(std::path::PathBuf),
+    #[error("'{0}' has no action '{1}'")]
+    NoAction(String, String),
+    #[error("malformed Exec key '{0}': {1}")]
+    BadExec(String, &'static str),
     #[error("error spawning selector process '{0}'")]
     Selector(String),
     #[error("selection cancelled")]
@@ -26,6 +32,21 @@ pub enum Error {
     NoTerminal,
     #[error("Bad path: {0}")]
     BadPath(String),
+    #[error(
+        "'{0}' is not a local file and no fetcher is configured to download it"
+    )]
+    NotLocal(String),
+    #[error("could not download '{0}': {1}")]
+    Fetch(String, String),
+    #[error(transparent)]
+    Json(#[from] json::Error),
+    #[cfg(feature = "dbus")]
+    #[error(transparent)]
+    DBus(#[from] zbus::Error),
+    #[error("unknown detection strategy '{0}', expected extension, content or combined")]
+    BadDetect(String),
+    #[error("nothing to undo")]
+    NothingToUndo,
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -7,13 +7,15 @@ mod cli;
 mod common;
 mod config;
 mod error;
+#[cfg(test)]
+mod testing;
 mod utils;
 
 fn main() -> Result<()> {
     use clap::Clap;
     use cli::Cmd;
-    use common::Handler;
-    use std::collections::HashMap;
+    use common::{Handler, UserPath};
+    use std::{collections::HashMap, time::Duration};
 
     // create config if it doesn't exist
     Lazy::force(&CONFIG);
@@ -23,39 +25,180 @@ fn main() -> Result<()> {
     let res = || -> Result<()> {
         match Cmd::parse() {
             Cmd::Set { mime, handler } => {
-                apps.set_handler(mime.0, handler);
-                apps.save()?;
+                apps.update(|apps| apps.set_handler(mime.0, handler))?;
             }
             Cmd::Add { mime, handler } => {
-                apps.add_handler(mime.0, handler);
-                apps.save()?;
+                apps.update(|apps| apps.add_handler(mime.0, handler))?;
             }
-            Cmd::Launch { mime, args } => {
-                apps.get_handler(&mime.0)?.launch(args.into_iter().map(|a| a.to_string()).collect())?;
+            Cmd::RemoveAssociation { mime, handler } => {
+                apps.update(|apps| apps.remove_association(mime.0, handler))?;
             }
-            Cmd::Get { mime, json } => {
-                apps.show_handler(&mime.0, json)?;
+            Cmd::Launch { mime, args, action } => {
+                let mut handler = apps.get_handler(&mime.0)?;
+                if let Some(action) = action {
+                    handler = handler.with_action(&action)?;
+                }
+                handler.launch(
+                    args.into_iter().map(|a| a.to_string()).collect(),
+                )?;
+            }
+            Cmd::Get {
+                mime,
+                json,
+                explain,
+            } => {
+                apps.show_handler(&mime.0, json, explain)?;
             }
-            Cmd::Open { paths } => {
+            Cmd::Why { mime, json } => {
+                apps.show_handler(&mime.0, json, true)?;
+            }
+            Cmd::Mime {
+                paths,
+                json,
+                detect,
+            } => {
+                let detect = detect.unwrap_or(CONFIG.detection);
+                let many = paths.len() > 1;
+                for path in paths {
+                    let detection = path.detect(detect);
+                    if json {
+                        let mut detection = detection.to_json();
+                        detection["path"] = path.to_string().into();
+                        println!("{}", detection);
+                    } else if many {
+                        println!("{}: {}", path, detection.mime);
+                    } else {
+                        println!("{}", detection.mime);
+                    }
+                }
+            }
+            Cmd::Open {
+                paths,
+                action,
+                dry_run,
+                json,
+                detect,
+            } => {
+                let detect = detect.unwrap_or(CONFIG.detection);
                 let mut handlers: HashMap<Handler, Vec<String>> =
                     HashMap::new();
+                let mut stdin = None;
 
                 for path in paths.into_iter() {
+                    // What is piped to handlr is opened from a file
+                    let path = match path {
+                        UserPath::File(f) if f.as_os_str() == "-" => {
+                            if stdin.is_none() {
+                                stdin = Some(common::Spool::new(
+                                    std::io::stdin().lock(),
+                                )?);
+                            }
+                            let spool = stdin.as_ref().unwrap();
+                            UserPath::File(spool.path.clone())
+                        }
+                        path => path,
+                    };
+
+                    // Links are opened like the URL they point to, apps
+                    // are launched
+                    let path = match path.desktop_entry() {
+                        Some(entry) => match &entry.url {
+                            Some(url) => url.parse()?,
+                            None => {
+                                let entry = match &action {
+                                    Some(action) => {
+                                        entry.with_action(action)?
+                                    }
+                                    None => entry,
+                                };
+                                if dry_run {
+                                    println!("{} is launched itself", path);
+                                } else {
+                                    entry.exec(
+                                        common::ExecMode::Launch,
+                                        vec![],
+                                    )?;
+                                }
+                                continue;
+                            }
+                        },
+                        None => path,
+                    };
+
+                    if dry_run {
+                        let trace =
+                            apps.trace(&path.get_mime(detect)?.0, false)?;
+                        if json {
+                            let mut trace = trace.to_json();
+                            trace["path"] = path.to_string().into();
+                            println!("{}", trace);
+                        } else {
+                            print!("{}\n{}", path, trace);
+                        }
+                        continue;
+                    }
+
                     handlers
-                        .entry(apps.get_handler(&path.get_mime()?.0)?)
+                        .entry(apps.get_handler(&path.get_mime(detect)?.0)?)
                         .or_default()
                         .push(path.to_string());
                 }
 
-                for (handler, paths) in handlers.into_iter() {
-                    handler.open(paths)?;
+                let stdin_path = stdin
+                    .as_ref()
+                    .map(|spool| spool.path.to_string_lossy().into_owned());
+                let mut spools = stdin.into_iter().collect::<Vec<_>>();
+                let mut spool_handlers = Vec::new();
+
+                for (mut handler, paths) in handlers.into_iter() {
+                    if let Some(action) = &action {
+                        handler = handler.with_action(action)?;
+                    }
+                    let opens_stdin =
+                        stdin_path.as_ref().is_some_and(|p| paths.contains(p));
+                    let (spawned, downloads) = handler.open(paths)?;
+                    if opens_stdin || !downloads.is_empty() {
+                        spool_handlers.extend(spawned);
+                    }
+                    spools.extend(downloads);
+                }
+
+                // Temporary files are removed once their handlers exit
+                if !dry_run {
+                    common::Spool::keep_until(
+                        spools,
+                        spool_handlers,
+                        Duration::from_secs(CONFIG.stdin_cleanup_delay),
+                    )?;
                 }
             }
-            Cmd::List { all } => {
+            Cmd::List { dangling: true, .. } => {
+                apps.print_dangling()?;
+            }
+            Cmd::List { all, .. } => {
                 apps.print(all)?;
             }
+            Cmd::Prune => {
+                apps.update(|apps| apps.prune())?;
+            }
+            Cmd::Cache {
+                cmd: cli::CacheCmd::Rebuild,
+            } => {
+                apps::rebuild_index()?;
+            }
+            Cmd::History => {
+                apps::MimeApps::print_history()?;
+            }
+            Cmd::Undo { count } => {
+                for change in apps.undo(count)? {
+                    eprintln!(
+                        "{} in {} was changed since, leaving it as is",
+                        change.mime, change.section
+                    );
+                }
+            }
             Cmd::Unset { mime } => {
-                apps.remove_handler(&mime.0)?;
+                apps.update(|apps| apps.remove_handler(&mime.0))?;
             }
             Cmd::Autocomplete {
                 desktop_files,
@@ -72,7 +215,7 @@ fn main() -> Result<()> {
     }();
 
     match (res, atty::is(atty::Stream::Stdout)) {
-        (Err(e), _) if matches!(e, Error::Cancelled) => {
+        (Err(Error::Cancelled), _) => {
             std::process::exit(1);
         }
         (Err(e), true) => {
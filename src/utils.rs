@@ -1,7 +1,27 @@
 use crate::Result;
 pub fn notify(title: &str, msg: &str) -> Result<()> {
     std::process::Command::new("notify-send")
-        .args(&["-t", "10000", title, msg])
+        .args(["-t", "10000", title, msg])
         .spawn()?;
-        Ok(())
+    Ok(())
+}
+
+/// Desktop environment names from `$XDG_CURRENT_DESKTOP`, in order
+pub fn current_desktops() -> Vec<String> {
+    std::env::var("XDG_CURRENT_DESKTOP")
+        .unwrap_or_default()
+        .split(':')
+        .filter(|d| !d.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// The locale messages are shown in, from `$LC_ALL`, `$LC_MESSAGES` or
+/// `$LANG`, whichever is set first
+pub fn messages_locale() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|locale| !locale.is_empty())
+        .filter(|locale| locale != "C" && locale != "POSIX")
 }